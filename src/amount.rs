@@ -0,0 +1,162 @@
+use std::fmt;
+use std::error::Error;
+use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
+use postgres::types::{FromSql, ToSql, Type, IsNull};
+
+// number of decimals of an amount when displayed
+pub const DECIMALS: usize = 8;
+// base units in one coin
+pub const COIN: u64 = 100000000;
+// largest amount, so that every amount and balance fits in a BIGINT
+pub const MAX_AMOUNT: u64 = 9223372036854775807;
+
+// an amount of coins counted in base units
+// it can't be negative and its arithmetic is checked, so that it never goes over the largest amount
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Default)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub fn zero() -> Amount {
+        Amount(0)
+    }
+
+    // none if the amount is over the largest amount
+    pub fn from_base_units(units: u64) -> Option<Amount> {
+        if units <= MAX_AMOUNT {
+            Some(Amount(units))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_base_units(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).and_then(Amount::from_base_units)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    // the amount left once the other one is taken, null if there isn't enough
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    // add up the given amounts, none if the total is over the largest amount
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts.into_iter().fold(Some(Amount::zero()), |total, amount| {
+            total.and_then(|total| total.checked_add(amount))
+        })
+    }
+
+    // get the amount as a signed number, to be added to or removed from a balance
+    pub fn to_i64(&self) -> i64 {
+        // can't overflow as the amount is never over the largest amount
+        self.0 as i64
+    }
+}
+
+// display in coins with every decimal, e.g. 12.50000000
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:0width$}", self.0 / COIN, self.0 % COIN, width = DECIMALS)
+    }
+}
+
+// encoded in JSON as a number of base units
+impl Encodable for Amount {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_u64(self.0)
+    }
+}
+
+impl Decodable for Amount {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Amount, D::Error> {
+        let units: i64 = d.read_i64()?;
+
+        // reject negative amounts as soon as they are read
+        if units < 0 {
+            return Err(d.error("negative amount"));
+        }
+
+        Ok(Amount(units as u64))
+    }
+}
+
+// stored as a BIGINT
+impl FromSql for Amount {
+    fn from_sql(ty: &Type, raw: &[u8]) -> Result<Amount, Box<Error + Sync + Send>> {
+        let units: i64 = i64::from_sql(ty, raw)?;
+
+        if units < 0 {
+            return Err("negative amount".into());
+        }
+
+        Ok(Amount(units as u64))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <i64 as FromSql>::accepts(ty)
+    }
+}
+
+impl ToSql for Amount {
+    fn to_sql(&self, ty: &Type, out: &mut Vec<u8>) -> Result<IsNull, Box<Error + Sync + Send>> {
+        self.to_i64().to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <i64 as ToSql>::accepts(ty)
+    }
+
+    fn to_sql_checked(&self, ty: &Type, out: &mut Vec<u8>) -> Result<IsNull, Box<Error + Sync + Send>> {
+        if !<Amount as ToSql>::accepts(ty) {
+            return Err(format!("can't store an amount as {}", ty.name()).into());
+        }
+
+        self.to_sql(ty, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::json;
+
+    #[test]
+    fn negative_amounts_are_rejected() {
+        assert_eq!(json::decode::<Amount>("125").ok(), Some(Amount(125)));
+        assert!(json::decode::<Amount>("-1").is_err());
+        assert!(json::decode::<Amount>("1.5").is_err());
+    }
+
+    #[test]
+    fn amounts_display_every_decimal() {
+        assert_eq!(Amount(1250000000).to_string(), "12.50000000");
+        assert_eq!(Amount(1).to_string(), "0.00000001");
+        assert_eq!(Amount::zero().to_string(), "0.00000000");
+        assert_eq!(Amount(MAX_AMOUNT).to_string(), "92233720368.54775807");
+    }
+
+    #[test]
+    fn arithmetic_stays_within_the_largest_amount() {
+        assert_eq!(Amount::from_base_units(MAX_AMOUNT), Some(Amount(MAX_AMOUNT)));
+        assert_eq!(Amount::from_base_units(MAX_AMOUNT + 1), None);
+
+        assert_eq!(Amount(MAX_AMOUNT - 1).checked_add(Amount(1)), Some(Amount(MAX_AMOUNT)));
+        assert_eq!(Amount(MAX_AMOUNT).checked_add(Amount(1)), None);
+        assert_eq!(Amount(2).checked_sub(Amount(3)), None);
+        assert_eq!(Amount(2).saturating_sub(Amount(3)), Amount::zero());
+
+        assert_eq!(Amount::checked_sum(vec![Amount(1), Amount(2), Amount(3)]), Some(Amount(6)));
+        assert_eq!(Amount::checked_sum(vec![Amount(MAX_AMOUNT), Amount(1)]), None);
+    }
+}
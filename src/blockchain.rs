@@ -1,12 +1,140 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Mutex;
 use r2d2::{Config, Pool};
 use r2d2_postgres::{TlsMode, PostgresConnectionManager};
+use postgres::GenericConnection;
+use postgres::rows::Row;
 use postgres_array::Array;
-use hex::FromHex;
+use hex::{FromHex, ToHex};
+use base58::ToBase58;
 
 use net::{NetBlock, NetTransaction};
-use errors::CoreError;
+use amount::Amount;
+use blocks::{self, Block, Header, HASH_SIZE};
+use errors::{CoreError, BlockError};
+use transactions;
+use utxo::{self, UtxoTransaction};
+use mempool;
+use difficulty;
+use validation;
+use orphans;
+use miner;
+use params;
+use state;
+
+lazy_static! {
+    // held while a block is processed, blocks received and mined on other threads are applied one at a time
+    // otherwise two blocks at the same height could both become the tip and both be applied to the state
+    static ref CHAIN_LOCK: Mutex<()> = Mutex::new(());
+}
 
-fn get_db_pool() -> Result<Pool<PostgresConnectionManager>, CoreError> {
+// number of blocks whose median timestamp a new block must be after
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+// blocks are stored as a tree indexed by hash, `main_chain` flags the blocks of the active branch
+// `chain_work` is the cumulative work up to the block, as a zero-padded hex u128 so it can be compared as text
+// `utxo_transactions` holds the raw UTXO transactions of version 2 blocks
+const SCHEMA: &'static str = "
+    DO $$ BEGIN
+        IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'payment') THEN
+            CREATE TYPE payment AS (
+                address TEXT,
+                amount BIGINT
+            );
+        END IF;
+
+        IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'tx') THEN
+            CREATE TYPE tx AS (
+                id TEXT,
+                sender_addr TEXT,
+                sender_pubkey TEXT,
+                receiver_addr TEXT,
+                amount BIGINT,
+                payments payment[],
+                fee BIGINT,
+                nonce BIGINT,
+                lock_time BIGINT,
+                data TEXT,
+                timestamp BIGINT,
+                signature_scheme SMALLINT,
+                signature TEXT
+            );
+        END IF;
+    END $$;
+
+    CREATE TABLE IF NOT EXISTS blocks (
+        hash TEXT PRIMARY KEY,
+        version INTEGER NOT NULL,
+        id INTEGER NOT NULL,
+        timestamp BIGINT NOT NULL,
+        previous_hash TEXT NOT NULL,
+        merkle_root TEXT NOT NULL,
+        difficulty INTEGER NOT NULL,
+        nonce BIGINT NOT NULL,
+        transactions tx[] NOT NULL,
+        chain_work TEXT NOT NULL,
+        main_chain BOOLEAN NOT NULL DEFAULT FALSE
+    );
+
+    ALTER TABLE blocks ADD COLUMN IF NOT EXISTS utxo_transactions TEXT[] NOT NULL DEFAULT '{}';
+
+    -- the tx type is only created once, add the attributes it gained since to older databases
+    -- transactions already stored get the given value instead of null
+    CREATE OR REPLACE FUNCTION add_tx_attribute(attribute TEXT, attribute_type TEXT, value JSONB) RETURNS VOID AS $$ BEGIN
+        IF NOT EXISTS (
+            SELECT 1 FROM pg_attribute WHERE attrelid = 'tx'::regclass AND attname = attribute AND NOT attisdropped
+        ) THEN
+            EXECUTE format('ALTER TYPE tx ADD ATTRIBUTE %I %s', attribute, attribute_type);
+            EXECUTE format(
+                'UPDATE blocks SET transactions = ARRAY(
+                    SELECT jsonb_populate_record(transactions[i], %L) FROM generate_subscripts(transactions, 1) AS i ORDER BY i
+                )',
+                jsonb_build_object(attribute, value)
+            );
+        END IF;
+    END $$ LANGUAGE plpgsql;
+
+    SELECT add_tx_attribute('nonce', 'BIGINT', '0');
+    SELECT add_tx_attribute('fee', 'BIGINT', '0');
+    SELECT add_tx_attribute('payments', 'payment[]', '[]');
+    SELECT add_tx_attribute('lock_time', 'BIGINT', '0');
+    SELECT add_tx_attribute('data', 'TEXT', '\"\"');
+    SELECT add_tx_attribute('signature_scheme', 'SMALLINT', '0');
+
+    -- amounts were INTEGER before they were counted in base units, widen them on older databases
+    -- a type used by a column can't be altered, the transactions are kept as text meanwhile
+    DO $$ BEGIN
+        IF EXISTS (
+            SELECT 1 FROM pg_attribute
+            WHERE attrelid IN ('tx'::regclass, 'payment'::regclass) AND attname IN ('amount', 'fee')
+                AND atttypid = 'integer'::regtype AND NOT attisdropped
+        ) THEN
+            ALTER TABLE blocks ALTER COLUMN transactions TYPE TEXT USING transactions::TEXT;
+            ALTER TYPE payment ALTER ATTRIBUTE amount TYPE BIGINT;
+            ALTER TYPE tx ALTER ATTRIBUTE amount TYPE BIGINT, ALTER ATTRIBUTE fee TYPE BIGINT;
+            ALTER TABLE blocks ALTER COLUMN transactions TYPE tx[] USING transactions::tx[];
+        END IF;
+    END $$;
+
+    CREATE INDEX IF NOT EXISTS blocks_previous_hash_idx ON blocks(previous_hash);
+    CREATE INDEX IF NOT EXISTS blocks_main_chain_id_idx ON blocks(id) WHERE main_chain;
+
+    CREATE TABLE IF NOT EXISTS tx_index (
+        tx_id TEXT NOT NULL,
+        block_hash TEXT NOT NULL,
+        PRIMARY KEY (tx_id, block_hash)
+    );
+
+    CREATE INDEX IF NOT EXISTS tx_index_block_hash_idx ON tx_index(block_hash);
+
+    CREATE TABLE IF NOT EXISTS chain_info (
+        network_id TEXT NOT NULL,
+        genesis_hash TEXT NOT NULL
+    );
+";
+
+pub fn get_db_pool() -> Result<Pool<PostgresConnectionManager>, CoreError> {
     let config = Config::default();
     let manager = PostgresConnectionManager::new(
         "postgres://mgul@localhost/blockchain",
@@ -19,36 +147,471 @@ fn get_db_pool() -> Result<Pool<PostgresConnectionManager>, CoreError> {
     }
 }
 
-pub fn add_block(block: NetBlock) -> Result<(), CoreError> {
+// create the tables if they don't exist yet and store the genesis of the selected network
+// fails if the database belongs to another network or has a different genesis
+pub fn init() -> Result<(), CoreError> {
     let pool = get_db_pool()?;
     let conn = pool.get().unwrap();
 
-    let query = "INSERT INTO blocks(id, timestamp, previous_hash, merkle_root, hash, nonce, transactions)
-        VALUES($1, $2, $3, $4, $5, $6, $7)";
-
-    conn.execute(query, &[
-        &block.id,
-        &block.timestamp,
-        &block.previous_hash,
-        &block.merkle_root,
-        &block.hash,
-        &block.nonce,
-        &Array::from_vec(block.transactions, 0)
+    conn.batch_execute(SCHEMA)?;
+    conn.batch_execute(state::SCHEMA)?;
+    conn.batch_execute(utxo::SCHEMA)?;
+
+    let params = params::get();
+    let genesis: Block = blocks::genesis();
+    let genesis_hash: String = genesis.header.get_hash().to_hex();
+
+    // the hardcoded genesis fields must produce the hardcoded hash
+    if genesis_hash != params.genesis.hash {
+        return Err(CoreError::ChainMismatch);
+    }
+
+    let rows = conn.query("SELECT network_id, genesis_hash FROM chain_info", &[])?;
+
+    if rows.is_empty() {
+        println!("STORE GENESIS {}", genesis_hash);
+
+        conn.execute(
+            "INSERT INTO chain_info(network_id, genesis_hash) VALUES($1, $2)",
+            &[&params.network_id, &genesis_hash]
+        )?;
+
+        store_block(&genesis)?;
+        select_chain(&genesis.header.get_hash()).map_err(|_| CoreError::DatabaseError)?;
+    } else {
+        let row = rows.get(0);
+        let network_id: String = row.get(0);
+        let stored_genesis_hash: String = row.get(1);
+
+        if network_id != params.network_id || stored_genesis_hash != genesis_hash {
+            return Err(CoreError::ChainMismatch);
+        }
+
+        // the balances table may be newer than the chain, fill it from the main chain's blocks
+        let rows = conn.query("SELECT 1 FROM balances LIMIT 1", &[])?;
+        if rows.is_empty() {
+            println!("REBUILD BALANCES");
+
+            let trans = conn.transaction()?;
+            let rows = trans.query("SELECT transactions FROM blocks WHERE main_chain ORDER BY id", &[])?;
+            for row in rows.iter() {
+                let transactions: Vec<NetTransaction> = row.get(0);
+                state::apply_transactions(&trans, &transactions, false)?;
+            }
+            trans.commit()?;
+        }
+
+        // the transaction index may be newer than the chain, fill it from every stored block
+        let rows = conn.query("SELECT 1 FROM tx_index LIMIT 1", &[])?;
+        if rows.is_empty() {
+            println!("REBUILD TRANSACTION INDEX");
+
+            let trans = conn.transaction()?;
+            let rows = trans.query("SELECT hash, transactions, utxo_transactions FROM blocks", &[])?;
+            for row in rows.iter() {
+                let hash: String = row.get(0);
+                let transactions: Vec<NetTransaction> = row.get(1);
+                let utxo_transactions: Vec<String> = row.get(2);
+
+                let mut tx_ids: Vec<String> = transactions.into_iter().map(|tx| tx.id).collect();
+                for tx in utxo_from_raw(utxo_transactions)? {
+                    tx_ids.push(tx.get_id().to_hex());
+                }
+
+                index_transactions(&trans, &hash, &tx_ids)?;
+            }
+            trans.commit()?;
+        }
+    }
+
+    Ok(())
+}
+
+// process a block received from the network or mined locally
+// orphans are kept until their parent arrives, valid blocks are stored and the branch with the most work becomes active
+pub fn process_block(net_block: NetBlock) -> Result<(), BlockError> {
+    let _lock = CHAIN_LOCK.lock().unwrap();
+
+    process_block_locked(net_block)
+}
+
+// process a block while holding the chain lock, then the orphans it is the parent of
+fn process_block_locked(net_block: NetBlock) -> Result<(), BlockError> {
+    let block = Block::from_net_block(&net_block)?;
+    let hash: Vec<u8> = block.header.get_hash();
+
+    // ignore blocks we already have
+    if get_header_by_hash(&hash)?.is_some() {
+        return Ok(());
+    }
+
+    let mined_hash: Vec<u8> = FromHex::from_hex(&net_block.hash).map_err(CoreError::from)?;
+
+    // the ids of the transactions are derived from them, the ones the block came with must match
+    validation::validate_transaction_ids(&net_block, &block)?;
+
+    match validation::validate_block(&block, &mined_hash) {
+        Err(BlockError::UnknownParent) => {
+            // we can't check the expected difficulty without the parent, but the hash must at least meet its own,
+            // which can't be much lower than the tip's, otherwise orphans could be made for free to fill the pool
+            let tip: Header = get_header_by_hash(&get_previous_hash()?)?.ok_or(CoreError::DatabaseError)?;
+            let min_difficulty: u32 = cmp::max(
+                difficulty::MIN_DIFFICULTY,
+                tip.difficulty.saturating_sub(difficulty::MAX_ADJUSTMENT)
+            );
+
+            if hash != mined_hash
+                || block.header.difficulty < min_difficulty
+                || !difficulty::meets_difficulty(&hash, block.header.difficulty) {
+                return Err(BlockError::InvalidProofOfWork);
+            }
+
+            if block.get_size() > blocks::MAX_BLOCK_SIZE {
+                return Err(BlockError::BlockTooLarge);
+            }
+
+            println!("ORPHAN BLOCK {}", hash.to_hex());
+            orphans::add(block.header.previous_hash.clone(), net_block);
+
+            return Ok(());
+        },
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+
+    store_block(&block)?;
+
+    match select_chain(&hash) {
+        // the tip changed, stop mining on the old one
+        Ok(true) => miner::interrupt(),
+        Ok(false) => {},
+        Err(e) => {
+            // the branch doesn't apply to the chain state, don't keep the block
+            remove_block(&hash)?;
+            return Err(e);
+        }
+    }
+
+    // connect the orphans that were waiting for this block
+    for child in orphans::take_children(&hash) {
+        if let Err(e) = process_block_locked(child) {
+            println!("ORPHAN REJECTED {:?}", e);
+        }
+    }
+
+    Ok(())
+}
+
+// store a block in the tree, outside of the main chain
+fn store_block(block: &Block) -> Result<(), CoreError> {
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    let header = &block.header;
+
+    // add the block's work to its parent's cumulative work
+    let parent_work: u128 = if header.previous_hash == vec![0; HASH_SIZE] {
+        0
+    } else {
+        get_chain_work(&*conn, &header.previous_hash)?
+    };
+    let chain_work: u128 = parent_work + difficulty::get_block_work(header.difficulty);
+
+    let net_block: NetBlock = NetBlock::from_block(block);
+    let tx_ids: Vec<String> = block.get_tx_hash_list().iter().map(|id| id.to_hex()).collect();
+
+    let trans = conn.transaction()?;
+
+    let query = "INSERT INTO blocks(
+            hash, version, id, timestamp, previous_hash, merkle_root, difficulty, nonce, transactions, chain_work,
+            utxo_transactions
+        ) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
+
+    trans.execute(query, &[
+        &net_block.hash,
+        &net_block.version,
+        &net_block.id,
+        &net_block.timestamp,
+        &net_block.previous_hash,
+        &net_block.merkle_root,
+        &net_block.difficulty,
+        &net_block.nonce,
+        &Array::from_vec(net_block.transactions, 0),
+        &format!("{:032x}", chain_work),
+        &Array::from_vec(net_block.utxo_transactions, 0)
     ])?;
 
+    index_transactions(&trans, &net_block.hash, &tx_ids)?;
+
+    trans.commit()?;
+
+    Ok(())
+}
+
+// map the ids of a block's transactions, account and UTXO ones, to the block
+fn index_transactions<C: GenericConnection>(conn: &C, block_hash: &String, tx_ids: &Vec<String>) -> Result<(), CoreError> {
+    for tx_id in tx_ids {
+        conn.execute(
+            "INSERT INTO tx_index(tx_id, block_hash) VALUES($1, $2) ON CONFLICT DO NOTHING",
+            &[tx_id, block_hash]
+        )?;
+    }
+
+    Ok(())
+}
+
+// delete a block which isn't part of the main chain
+fn remove_block(hash: &Vec<u8>) -> Result<(), CoreError> {
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    let trans = conn.transaction()?;
+
+    let deleted = trans.execute("DELETE FROM blocks WHERE hash = $1 AND NOT main_chain", &[&hash.to_hex()])?;
+    if deleted > 0 {
+        trans.execute("DELETE FROM tx_index WHERE block_hash = $1", &[&hash.to_hex()])?;
+    }
+
+    trans.commit()?;
+
+    Ok(())
+}
+
+// get the cumulative work up to the given block
+fn get_chain_work<C: GenericConnection>(conn: &C, hash: &Vec<u8>) -> Result<u128, CoreError> {
+    let rows = conn.query("SELECT chain_work FROM blocks WHERE hash = $1", &[&hash.to_hex()])?;
+
+    if rows.is_empty() {
+        return Err(CoreError::DatabaseError);
+    }
+
+    let chain_work: String = rows.get(0).get(0);
+
+    u128::from_str_radix(&chain_work, 16).map_err(|_| CoreError::SerializeError)
+}
+
+// make the branch ending with the given block active if it has more work than the current tip
+// blocks of the old branch are disconnected and the new branch is connected in a single db transaction
+// returns true if the tip changed, fails without changing anything if the new branch doesn't apply to the chain state
+fn select_chain(candidate: &Vec<u8>) -> Result<bool, BlockError> {
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    // on equal work the first seen branch is kept
+    let rows = conn.query("SELECT chain_work FROM blocks WHERE main_chain ORDER BY id DESC LIMIT 1", &[])?;
+    if !rows.is_empty() {
+        let tip_work: String = rows.get(0).get(0);
+        let candidate_work = format!("{:032x}", get_chain_work(&*conn, candidate)?);
+
+        if candidate_work <= tip_work {
+            return Ok(false);
+        }
+    }
+
+    // walk the candidate branch back to the fork point with the main chain
+    let zero_hash: Vec<u8> = vec![0; HASH_SIZE];
+    let mut to_connect: Vec<Vec<u8>> = Vec::new();
+    let mut cursor: Vec<u8> = candidate.clone();
+    let mut fork_id: i32 = -1;
+
+    while cursor != zero_hash {
+        let rows = conn.query(
+            "SELECT id, previous_hash, main_chain FROM blocks WHERE hash = $1", &[&cursor.to_hex()]
+        )?;
+        if rows.is_empty() {
+            return Err(BlockError::CoreError);
+        }
+
+        let row = rows.get(0);
+        let id: i32 = row.get(0);
+        let previous_hash: String = row.get(1);
+        let main_chain: bool = row.get(2);
+
+        if main_chain {
+            fork_id = id;
+            break;
+        }
+
+        to_connect.push(cursor);
+        cursor = FromHex::from_hex(previous_hash)?;
+    }
+
+    // blocks of the main chain above the fork point, from the tip down
+    let rows = conn.query(
+        "SELECT hash FROM blocks WHERE main_chain AND id > $1 ORDER BY id DESC", &[&fork_id]
+    )?;
+    let to_disconnect: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+
+    if !to_disconnect.is_empty() {
+        println!("REORG: DISCONNECT {} BLOCKS, CONNECT {} BLOCKS", to_disconnect.len(), to_connect.len());
+    }
+
+    let trans = conn.transaction()?;
+
+    let mut disconnected_txs: Vec<NetTransaction> = Vec::new();
+    let mut disconnected_utxo_txs: Vec<UtxoTransaction> = Vec::new();
+    for hash in &to_disconnect {
+        let (txs, utxo_txs) = disconnect_block(&trans, hash)?;
+        disconnected_txs.extend(txs);
+        disconnected_utxo_txs.extend(utxo_txs);
+    }
+
+    let mut connected_txs: Vec<NetTransaction> = Vec::new();
+    let mut connected_utxo_txs: Vec<UtxoTransaction> = Vec::new();
+    for hash in to_connect.iter().rev() {
+        let (txs, utxo_txs) = connect_block(&trans, &hash.to_hex())?;
+        connected_txs.extend(txs);
+        connected_utxo_txs.extend(utxo_txs);
+    }
+
+    trans.commit()?;
+
+    // the new tip decides which locked transactions can be included in the next block
+    mempool::set_height(fork_id + to_connect.len() as i32);
+
+    update_mempool(disconnected_txs, connected_txs)?;
+    update_utxo_mempool(disconnected_utxo_txs, connected_utxo_txs)?;
+
+    Ok(true)
+}
+
+// add a stored block to the main chain, apply it to the balances and the UTXO set and return its transactions
+fn connect_block<C: GenericConnection>(
+    conn: &C,
+    hash: &String
+) -> Result<(Vec<NetTransaction>, Vec<UtxoTransaction>), BlockError> {
+    let rows = conn.query("SELECT id, transactions, utxo_transactions FROM blocks WHERE hash = $1", &[hash])?;
+    if rows.is_empty() {
+        return Err(BlockError::CoreError);
+    }
+
+    let row = rows.get(0);
+    let id: i32 = row.get(0);
+    let transactions: Vec<NetTransaction> = row.get(1);
+    let utxo_transactions: Vec<UtxoTransaction> = utxo_from_raw(row.get(2))?;
+
+    // check the block against the state left by its parent
+    validation::validate_block_state(conn, id, &transactions, &utxo_transactions)?;
+
+    conn.execute("UPDATE blocks SET main_chain = TRUE WHERE hash = $1", &[hash])?;
+    state::apply_transactions(conn, &transactions, false)?;
+    utxo::apply_transactions(conn, hash, &utxo_transactions, false)?;
+
+    Ok((transactions, utxo_transactions))
+}
+
+// remove a block from the main chain, revert it from the balances and the UTXO set and return its transactions
+fn disconnect_block<C: GenericConnection>(
+    conn: &C,
+    hash: &String
+) -> Result<(Vec<NetTransaction>, Vec<UtxoTransaction>), CoreError> {
+    let rows = conn.query(
+        "UPDATE blocks SET main_chain = FALSE WHERE hash = $1 RETURNING transactions, utxo_transactions", &[hash]
+    )?;
+    if rows.is_empty() {
+        return Err(CoreError::DatabaseError);
+    }
+
+    let row = rows.get(0);
+    let transactions: Vec<NetTransaction> = row.get(0);
+    let utxo_transactions: Vec<UtxoTransaction> = utxo_from_raw(row.get(1))?;
+
+    state::apply_transactions(conn, &transactions, true)?;
+    utxo::apply_transactions(conn, hash, &utxo_transactions, true)?;
+
+    Ok((transactions, utxo_transactions))
+}
+
+// decode the raw UTXO transactions of a stored block
+fn utxo_from_raw(raws: Vec<String>) -> Result<Vec<UtxoTransaction>, CoreError> {
+    let mut transactions: Vec<UtxoTransaction> = Vec::new();
+    for raw in &raws {
+        transactions.push(UtxoTransaction::from_raw(raw)?);
+    }
+
+    Ok(transactions)
+}
+
+// drop the transactions included in the new branch from the mempool and put back the ones of disconnected blocks
+// then drop the pending transactions the new branch makes invalid
+fn update_mempool(
+    disconnected_txs: Vec<NetTransaction>,
+    connected_txs: Vec<NetTransaction>
+) -> Result<(), CoreError> {
+    let mut connected_ids: Vec<Vec<u8>> = Vec::new();
+    for net_tx in &connected_txs {
+        connected_ids.push(FromHex::from_hex(&net_tx.id)?);
+    }
+
+    mempool::remove(&connected_ids);
+
+    for net_tx in disconnected_txs {
+        let tx = transactions::from_net(&net_tx)?;
+
+        // coinbase transactions are only valid in their own block
+        if !tx.is_coinbase() && !connected_ids.contains(&tx.id) {
+            mempool::add(tx);
+        }
+    }
+
+    // pending transactions must still follow the nonces and fit in the balances of the new branch
+    // (a transaction of the new branch may have used their nonce or spent their funds)
+    let mut confirmed: HashMap<Vec<u8>, (Amount, u64)> = HashMap::new();
+    for sender in mempool::get_senders() {
+        let address: String = sender.to_base58();
+        let balance: Amount = state::get_confirmed_balance(&address)?;
+        let nonce: u64 = state::get_confirmed_nonce(&address)?;
+        confirmed.insert(sender, (balance, nonce));
+    }
+
+    mempool::revalidate(&confirmed);
+
+    Ok(())
+}
+
+// same as `update_mempool` for the UTXO transactions
+// the transactions of disconnected blocks go back to the mempool if they still apply on top of the new branch
+fn update_utxo_mempool(
+    disconnected_txs: Vec<UtxoTransaction>,
+    connected_txs: Vec<UtxoTransaction>
+) -> Result<(), CoreError> {
+    let connected_ids: Vec<Vec<u8>> = connected_txs.iter().map(|tx| tx.get_id()).collect();
+
+    mempool::remove(&connected_ids);
+
+    if disconnected_txs.is_empty() {
+        return Ok(());
+    }
+
+    let (_, mut view) = utxo::get_pending()?;
+
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    for tx in disconnected_txs {
+        if tx.is_coinbase() || connected_ids.contains(&tx.get_id()) {
+            continue;
+        }
+
+        if let Ok(fee) = view.apply(&*conn, &tx) {
+            mempool::add_utxo(tx, fee);
+        }
+    }
+
     Ok(())
 }
 
+// get the height of the main chain's tip (0 if the chain is empty)
 pub fn get_previous_id() -> Result<i32, CoreError> {
     let pool = get_db_pool()?;
     let conn = pool.get().unwrap();
 
-    let query = "SELECT id FROM blocks ORDER BY id DESC";
+    let query = "SELECT id FROM blocks WHERE main_chain ORDER BY id DESC LIMIT 1";
     let rows = conn.query(query, &[])?;
 
     if !rows.is_empty() {
         let row = rows.get(0);
-        let id: i32 = row.get("id"); // TODO access by index (more efficient)
+        let id: i32 = row.get(0);
 
         Ok(id)
     } else {
@@ -56,99 +619,139 @@ pub fn get_previous_id() -> Result<i32, CoreError> {
     }
 }
 
+// get the hash of the main chain's tip
 pub fn get_previous_hash() -> Result<Vec<u8>, CoreError> {
     let pool = get_db_pool()?;
     let conn = pool.get().unwrap();
 
-    let query = "SELECT hash FROM blocks ORDER BY id DESC";
+    let query = "SELECT hash FROM blocks WHERE main_chain ORDER BY id DESC LIMIT 1";
     let rows = conn.query(query, &[])?;
 
     if !rows.is_empty() {
         let row = rows.get(0);
-        let hash: String = row.get("hash"); // TODO access by index (more efficient)
+        let hash: String = row.get(0);
         let hash_bytes: Vec<u8> = FromHex::from_hex(hash)?;
 
         Ok(hash_bytes)
     } else {
-        // genesis
-        let zero_hash: Vec<u8> = vec![0];
-        Ok(zero_hash)
+        // the genesis is stored at startup so this only happens on an uninitialized database
+        Err(CoreError::DatabaseError)
     }
 }
 
-#[derive(Debug)]
-struct LedgerEntry {
-    sender_addr: String,
-    receiver_addr: String,
-    amount: i32
-}
-
-pub fn scan() -> Result<(), CoreError> {
+// get the header of the block with the given hash, on any branch, if we know it
+pub fn get_header_by_hash(hash: &Vec<u8>) -> Result<Option<Header>, CoreError> {
     let pool = get_db_pool()?;
     let conn = pool.get().unwrap();
 
-    let query = "SELECT transactions FROM blocks";
-    let rows = conn.query(query, &[])?;
-
-    let mut ledger: Vec<Vec<(String, String, i32)>> = Vec::new();
+    let query = "SELECT version, id, timestamp, previous_hash, merkle_root, difficulty, nonce
+        FROM blocks WHERE hash = $1";
+    let rows = conn.query(query, &[&hash.to_hex()])?;
 
     if !rows.is_empty() {
-        for (i, _) in rows.iter().enumerate() {
-            let transactions: Vec<NetTransaction> = rows.get(i).get(0);
-
-            let block_ledger: Vec<(String, String, i32)> = transactions.into_iter().map(|tx| {
-                (tx.sender_addr, tx.receiver_addr, tx.amount)
-            }).collect();
-
-            ledger.push(block_ledger);
-        }
+        Ok(Some(header_from_row(&rows.get(0))?))
     } else {
-        return Err(CoreError::IoError); // XXX proper handle
+        Ok(None)
     }
+}
 
-    let mut ins: Vec<(String, i32)> = Vec::new();
-    let mut outs: Vec<(String, i32)> = Vec::new();
+// get the median timestamp of the given block and its ancestors, `MEDIAN_TIME_SPAN` blocks at most
+// a new block on top of it must be timestamped after it
+pub fn get_median_time_past(header: &Header) -> Result<i64, CoreError> {
+    let mut timestamps: Vec<i64> = vec![header.timestamp];
+    let mut ancestor: Header = header.clone();
+
+    // the genesis has no parent
+    while timestamps.len() < MEDIAN_TIME_SPAN && ancestor.id > 0 {
+        ancestor = match get_header_by_hash(&ancestor.previous_hash)? {
+            Some(parent) => parent,
+            None => return Err(CoreError::DatabaseError)
+        };
+        timestamps.push(ancestor.timestamp);
+    }
 
-    println!("{:?}", ledger);
-    println!("------\n");
+    timestamps.sort();
+    Ok(timestamps[timestamps.len() / 2])
+}
 
-    for block_ledger in ledger {
-        for ledger_entry in block_ledger {
-            ins.push((ledger_entry.1, ledger_entry.2));
-            outs.push((ledger_entry.0, ledger_entry.2));
-        }
+// get the ancestor `depth` blocks above the given header, following the header's branch
+pub fn get_ancestor(header: &Header, depth: i32) -> Result<Header, CoreError> {
+    let mut ancestor: Header = header.clone();
+
+    for _ in 0..depth {
+        ancestor = match get_header_by_hash(&ancestor.previous_hash)? {
+            Some(parent) => parent,
+            None => return Err(CoreError::DatabaseError)
+        };
     }
 
-    let mut balances: Vec<(String, i32)> = Vec::new();
-    let mut in_balances: Vec<(String, i32)> = Vec::new();
-    let mut addresses: Vec<String> = Vec::new();
+    Ok(ancestor)
+}
 
-    println!("INS: {:?}\n", ins);
-    println!("OUTS: {:?}\n", outs);
+// check if a transaction with the given id is part of the main chain
+pub fn contains_transaction(tx_id: &String) -> Result<bool, CoreError> {
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
 
-    for (i, e) in ins.into_iter().enumerate() {
-        println!("[{}] | {:?}", i, e);
-        if !addresses.contains(&e.0) {
-            addresses.push(e.0);
-        }
-    }
+    is_in_main_chain(&*conn, tx_id)
+}
 
-    println!("\n");
+pub fn is_in_main_chain<C: GenericConnection>(conn: &C, tx_id: &String) -> Result<bool, CoreError> {
+    let query = "SELECT 1 FROM tx_index
+        JOIN blocks ON blocks.hash = tx_index.block_hash
+        WHERE tx_index.tx_id = $1 AND blocks.main_chain";
+    let rows = conn.query(query, &[tx_id])?;
 
-    for (i, e) in outs.into_iter().enumerate() {
-        println!("[{}] | {:?}", i, e);
-        if !addresses.contains(&e.0) && e.0 != "0" {
-            addresses.push(e.0);
-        }
-    }
+    Ok(!rows.is_empty())
+}
 
-    println!("\n");
+// get the main chain's block including the transaction with the given id
+pub fn get_block_by_tx_id(tx_id: &String) -> Result<Option<NetBlock>, CoreError> {
+    let pool = get_db_pool()?;
+    let conn = pool.get().unwrap();
 
-    println!("{:?}", addresses);
+    let query = "SELECT version, id, timestamp, previous_hash, merkle_root, difficulty, nonce, hash, transactions,
+            utxo_transactions
+        FROM tx_index
+        JOIN blocks ON blocks.hash = tx_index.block_hash
+        WHERE tx_index.tx_id = $1 AND blocks.main_chain";
+    let rows = conn.query(query, &[tx_id])?;
 
-    println!("\n");
+    if !rows.is_empty() {
+        Ok(Some(net_block_from_row(&rows.get(0))))
+    } else {
+        Ok(None)
+    }
+}
 
-    println!("{:?}\n", balances);
+// rebuild a network block from a row selecting
+// (version, id, timestamp, previous_hash, merkle_root, difficulty, nonce, hash, transactions, utxo_transactions)
+fn net_block_from_row(row: &Row) -> NetBlock {
+    NetBlock {
+        version: row.get(0),
+        id: row.get(1),
+        timestamp: row.get(2),
+        previous_hash: row.get(3),
+        merkle_root: row.get(4),
+        difficulty: row.get(5),
+        nonce: row.get(6),
+        hash: row.get(7),
+        transactions: row.get(8),
+        utxo_transactions: row.get(9)
+    }
+}
 
-    Ok(())
+// rebuild a header from a row selecting (version, id, timestamp, previous_hash, merkle_root, difficulty, nonce)
+fn header_from_row(row: &Row) -> Result<Header, CoreError> {
+    let version: i32 = row.get(0);
+    let id: i32 = row.get(1);
+    let timestamp: i64 = row.get(2);
+    let previous_hash: String = row.get(3);
+    let merkle_root: String = row.get(4);
+    let difficulty: i32 = row.get(5);
+    let nonce: i64 = row.get(6);
+
+    Header::from(
+        version as u32, id, timestamp, &previous_hash, &merkle_root, difficulty as u32, nonce as u64
+    )
 }
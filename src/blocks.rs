@@ -1,266 +1,418 @@
-use bincode::{serialize, Infinite};
+use std::cmp::{self, Reverse};
+use std::collections::{HashMap, HashSet};
 use sha2::{Sha256, Digest};
 use hex::{FromHex, ToHex};
-use rand::{self, Rng};
+use base58::ToBase58;
 
+use amount::Amount;
 use errors::CoreError;
 use blockchain;
+use difficulty;
+use mempool;
+use merkle;
+use miner;
 use transactions::{self, Transaction};
+use utxo::{self, UtxoTransaction, UtxoView};
 use net::NetBlock;
+use monetary;
+use state;
 use utils;
+use params;
+
+// version of the block header layout
+pub const BLOCK_VERSION: u32 = 1;
+// blocks carrying UTXO transactions, their coinbase is the first UTXO transaction
+pub const BLOCK_VERSION_UTXO: u32 = 2;
+// size of the hashes committed in the header
+pub const HASH_SIZE: usize = 32;
+// size of the serialized header (version, id, previous_hash, merkle_root, timestamp, difficulty, nonce)
+pub const HEADER_SIZE: usize = 4 + 4 + HASH_SIZE + HASH_SIZE + 8 + 4 + 8;
+// maximum size of a block, header and serialized transactions included
+pub const MAX_BLOCK_SIZE: usize = 1000000;
+// room left for the coinbase when filling a block template
+const COINBASE_RESERVED_SIZE: usize = 1000;
 
 // FIXME bad: everything is public
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Header {
+    pub version: u32,
     pub id: i32,
-    pub timestamp: i64,
     pub previous_hash: Vec<u8>,
     pub merkle_root: Vec<u8>,
+    pub timestamp: i64,
+    pub difficulty: u32,
+    pub nonce: u64,
 }
 
-// TODO implement previous_hash
 // TODO maybe make this private and return a "web" Block (for easier JSON) instead of this struct
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Block {
     pub header: Header,
-    pub hash: Vec<u8>, // XXX place this in Header?
-    pub nonce: i64, // XXX place this in Header?
     pub transactions: Vec<Transaction>,
+    pub utxo_transactions: Vec<UtxoTransaction>,
 }
 
 impl Header {
     pub fn from(
+        version: u32,
         id: i32,
         timestamp: i64,
         previous_hash: &String,
-        merkle_root: &String
+        merkle_root: &String,
+        difficulty: u32,
+        nonce: u64
     ) -> Result<Header, CoreError> {
         let previous_hash: Vec<u8> = FromHex::from_hex(previous_hash)?;
         let merkle_root: Vec<u8> = FromHex::from_hex(merkle_root)?;
 
+        // hashes have a fixed size in the serialized header
+        if previous_hash.len() != HASH_SIZE || merkle_root.len() != HASH_SIZE {
+            return Err(CoreError::SerializeError);
+        }
+
         Ok(Header {
+            version: version,
             id: id,
-            timestamp: timestamp,
             previous_hash: previous_hash,
-            merkle_root: merkle_root
+            merkle_root: merkle_root,
+            timestamp: timestamp,
+            difficulty: difficulty,
+            nonce: nonce
         })
     }
+
+    // serialize the header into its canonical binary layout
+    // every integer is little-endian, hashes are written as raw 32 bytes:
+    // version (u32) | id (u32) | previous_hash | merkle_root | timestamp (i64) | difficulty (u32) | nonce (u64)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoded: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+
+        push_le(&mut encoded, self.version as u64, 4);
+        push_le(&mut encoded, self.id as u32 as u64, 4);
+        encoded.extend_from_slice(&self.previous_hash);
+        encoded.extend_from_slice(&self.merkle_root);
+        push_le(&mut encoded, self.timestamp as u64, 8);
+        push_le(&mut encoded, self.difficulty as u64, 4);
+        // the nonce is always last so miners can rewrite it in place
+        push_le(&mut encoded, self.nonce, 8);
+
+        encoded
+    }
+
+    // hash the serialized header to get the block hash
+    pub fn get_hash(&self) -> Vec<u8> {
+        get_header_hash(&self.to_bytes())
+    }
 }
 
 impl Block {
-    pub fn from(
-        id: i32,
-        timestamp: i64,
-        previous_hash: String,
-        merkle_root: String,
-        hash: String,
-        nonce: i64,
-        transactions: Vec<Transaction>
-    ) -> Result<Block, CoreError> {
-        let previous_hash: Vec<u8> = FromHex::from_hex(previous_hash)?;
-        let merkle_root: Vec<u8> = FromHex::from_hex(merkle_root)?;
-        let hash: Vec<u8> = FromHex::from_hex(hash)?;
+    // create a block from a network block
+    pub fn from_net_block(block: &NetBlock) -> Result<Block, CoreError> {
+        let header = Header::from(
+            block.version as u32,
+            block.id,
+            block.timestamp,
+            &block.previous_hash,
+            &block.merkle_root,
+            block.difficulty as u32,
+            block.nonce as u64
+        )?;
+
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for tx in &block.transactions {
+            transactions.push(transactions::from_net(tx)?);
+        }
+
+        let mut utxo_transactions: Vec<UtxoTransaction> = Vec::new();
+        for raw in &block.utxo_transactions {
+            utxo_transactions.push(UtxoTransaction::from_raw(raw)?);
+        }
 
         Ok(Block {
-            header: Header {
-                id: id,
-                timestamp: timestamp,
-                previous_hash: previous_hash,
-                merkle_root: merkle_root
-            },
-            hash: hash,
-            nonce: nonce,
-            transactions: transactions
+            header: header,
+            transactions: transactions,
+            utxo_transactions: utxo_transactions
         })
     }
+
+    // get the size of the header and of every serialized transaction
+    pub fn get_size(&self) -> usize {
+        HEADER_SIZE
+            + self.transactions.iter().map(|tx| tx.get_size()).sum::<usize>()
+            + self.utxo_transactions.iter().map(|tx| tx.get_size()).sum::<usize>()
+    }
+
+    // get the hash list of every transaction id in the block, account transactions first
+    pub fn get_tx_hash_list(&self) -> Vec<Vec<u8>> {
+        let mut tx_hash_list: Vec<Vec<u8>> = self.transactions.iter().map(|tx| tx.id.clone()).collect();
+        tx_hash_list.extend(self.utxo_transactions.iter().map(|tx| tx.get_id()));
+
+        tx_hash_list
+    }
 }
 
-// TODO add transactions dynamically to the block as they come
-// (recalculate merkle_root for every new transaction and try to mine the new merkle_root)
-pub fn new() -> Result<(), CoreError> {
+// build the hardcoded genesis block of the selected network
+// it has no transaction, so its merkle root is null
+pub fn genesis() -> Block {
+    let params = params::get();
+
+    Block {
+        header: Header {
+            version: BLOCK_VERSION,
+            id: 0,
+            previous_hash: vec![0; HASH_SIZE],
+            merkle_root: vec![0; HASH_SIZE],
+            timestamp: params.genesis.timestamp,
+            difficulty: params.initial_difficulty,
+            nonce: params.genesis.nonce
+        },
+        transactions: Vec::new(),
+        utxo_transactions: Vec::new()
+    }
+}
+
+// get the version of the block at the given height
+pub fn get_block_version(id: i32) -> u32 {
+    if id >= params::get().utxo_activation_height {
+        BLOCK_VERSION_UTXO
+    } else {
+        BLOCK_VERSION
+    }
+}
+
+// hash a serialized header
+pub fn get_header_hash(header_encoded: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(header_encoded);
+    hasher.result().as_slice().to_vec()
+}
+
+// write the `size` lowest bytes of a value in little-endian order
+fn push_le(buf: &mut Vec<u8>, value: u64, size: usize) {
+    for i in 0..size {
+        buf.push((value >> (8 * i)) as u8);
+    }
+}
+
+// mine a new block paying the configured reward address on top of the main chain's tip
+// returns true if a block was mined and accepted
+pub fn new() -> Result<bool, CoreError> {
     println!("CREATE BLOCK");
 
-    let id: i32 = blockchain::get_previous_id()? + 1;
-    let timestamp: i64 = utils::get_current_timestamp();
+    // capture the mining epoch before reading the template so that any change after this point aborts mining
+    let epoch: usize = miner::get_epoch();
+
+    let template: Block = new_template(miner::get_payout_address()?)?;
 
-    // get last cached transactions from database
-    let mut transactions = transactions::read_db()?;
+    let header = match miner::mine(&template.header, epoch) {
+        Some(header) => header,
+        // the template is obsolete, drop this block
+        None => return Ok(false)
+    };
+
+    let block: Block = Block {
+        header: header,
+        transactions: template.transactions,
+        utxo_transactions: template.utxo_transactions
+    };
 
-    // create coinbase transaction for reward
-    let coinbase_transaction = transactions::coinbase()?;
+    // create network block with block
+    let net_block: NetBlock = NetBlock::from_block(&block);
+
+    // store the block and make it our tip if it still extends the best chain
+    match blockchain::process_block(net_block) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            println!("MINED BLOCK REJECTED {:?}", e);
+            Ok(false)
+        }
+    }
+}
 
-    // insert coinbase transaction at begining of transactions
-    transactions.insert(0, coinbase_transaction);
+// TODO add transactions dynamically to the block as they come
+// (recalculate merkle_root for every new transaction and try to mine the new merkle_root)
+// build the next block on top of the main chain's tip, with a null nonce left to be mined
+pub fn new_template(reward_address: Vec<u8>) -> Result<Block, CoreError> {
+    println!("CREATE BLOCK TEMPLATE");
 
-    // delete all cached transactions
-    // transactions::clean_db() // XXX don't uncomment now because we retrieve from database again below
+    // build on top of the main chain's tip
+    let id: i32 = blockchain::get_previous_id()? + 1;
+    let previous_hash: Vec<u8> = blockchain::get_previous_hash()?;
+    let previous: Header = blockchain::get_header_by_hash(&previous_hash)?.ok_or(CoreError::DatabaseError)?;
+    // the block must be timestamped after the median time past, even if our clock is behind
+    let median_time_past: i64 = blockchain::get_median_time_past(&previous)?;
+    let timestamp: i64 = cmp::max(utils::get_current_timestamp(), median_time_past + 1);
+    let version: u32 = get_block_version(id);
+
+    // pick the pending transactions paying the best fee rates that fit in the block
+    let max_size: usize = MAX_BLOCK_SIZE - HEADER_SIZE - COINBASE_RESERVED_SIZE;
+    let (mut transactions, selected, fees) = select_transactions(max_size, id, median_time_past, version == BLOCK_VERSION_UTXO)?;
+
+    let mut utxo_transactions: Vec<UtxoTransaction> = Vec::new();
+
+    // create coinbase transaction for reward, collecting the fees of every included transaction
+    if version == BLOCK_VERSION_UTXO {
+        let coinbase_amount: Amount = monetary::get_max_coinbase_amount(id, fees).ok_or(CoreError::AmountOverflow)?;
+        utxo_transactions.push(utxo::coinbase(id, coinbase_amount, reward_address));
+        utxo_transactions.extend(selected);
+    } else {
+        let coinbase_transaction = transactions::coinbase(id, fees, reward_address)?;
 
-    // create a hash list with all tx ids
-    // XXX this consumes the transactions vec, but we need it again later
-    // let tx_hash_list: Vec<Vec<u8>> = transactions.into_iter()
-    //     .map(|tx| tx.id)
-    //     .collect();
+        // insert coinbase transaction at begining of transactions
+        transactions.insert(0, coinbase_transaction);
+    }
 
     let mut tx_hash_list: Vec<Vec<u8>> = Vec::new();
     for tx in &transactions {
         tx_hash_list.push(tx.id.clone()); // XXX maybe there is a better way than calling `clone()`
     }
+    for tx in &utxo_transactions {
+        tx_hash_list.push(tx.get_id());
+    }
 
     // get merkle root of all tx using the hash list
-    let merkle_root: Vec<u8> = get_merkle_root(&tx_hash_list);
+    let merkle_root: Vec<u8> = merkle::get_merkle_root(&tx_hash_list);
 
-    // get previous block's hash to include in header
-    let previous_hash: Vec<u8> = blockchain::get_previous_hash()?;
+    // get the difficulty required on top of the previous block
+    let difficulty: u32 = difficulty::get_expected_difficulty(&previous_hash)?;
 
     println!("\nBLOCK INFOS\n------");
     println!("id: {}", id);
+    println!("version: {}", version);
     println!("timestamp: {}", timestamp);
     println!("merkle_root: {}", merkle_root.to_hex());
-    println!("previous_hash: {}\n", previous_hash.to_hex());
+    println!("previous_hash: {}", previous_hash.to_hex());
+    println!("difficulty: {}\n", difficulty);
 
     let header: Header = Header {
+        version: version,
         id: id,
-        timestamp: timestamp,
         previous_hash: previous_hash,
         merkle_root: merkle_root,
+        timestamp: timestamp,
+        difficulty: difficulty,
+        nonce: 0
     };
 
-    let (hash, nonce) = mine(&header)?;
-
-    // FIXME bad! we read database two times (should use previous transactions Vec)
-    // let transactions = transactions::read_db()?;
-
-    let block: Block = Block {
+    Ok(Block {
         header: header,
-        hash: hash,
-        nonce: nonce,
-        transactions: transactions // FIXME use the previous transactions Vec instead!
-    };
-
-    // create network block with block
-    let net_block: NetBlock = NetBlock::from_block(block);
-
-    blockchain::add_block(net_block);
-    // store_db(&block)?;
-
-    Ok(())
+        transactions: transactions,
+        utxo_transactions: utxo_transactions
+    })
 }
 
-// mine a block with the block's header
-fn mine(header: &Header) -> Result<(Vec<u8>, i64), CoreError> {
-    println!("MINE BLOCK");
-
-    // serialize the block header
-    let header_encoded: Vec<u8> = serialize(header, Infinite)?;
-
-    // hash the block header
-    let mut hasher = Sha256::default();
-    hasher.input(&header_encoded);
-    let header_hashed: Vec<u8> = hasher.result().as_slice().to_vec();
-
-    // make a proof of work using this hash
-    Ok(proof_of_work(&header_hashed)?)
-}
-
-// TODO remake this with bytes not strings
-// TODO spawn new thread for concurrent mining
-fn proof_of_work(hash: &Vec<u8>) -> Result<(Vec<u8>, i64), CoreError> {
-    println!("PROOF OF WORK...");
-
-    let mut rng = rand::thread_rng(); // TODO check if we can reuse this (is it secure) or should we recreate one every time
-    // XXX what if `nonce: i64` isn't big enough to hold the value that will allow to find the correct hash?
-    let mut nonce: i64 = 0;
-    let n: usize = 2; // this is basically the difficulty (n is bigger -> less probability to find a good hash)
-    let mut hash_final = hash.clone().to_hex();
-
-    // while the leading bytes aren't some 0s
-    while &hash_final[..n] != &(0..n).map(|_| "0").collect::<String>() {
-        // generate a new random nonce
-        nonce = rng.gen::<i64>();
-        // concat the hash and the nonce
-        hash_final = format!("{}{}", hash.to_hex(), nonce);
-
-        // hash the whole thing
-        let mut hasher = Sha256::default();
-        hasher.input(hash_final.as_bytes());
-        hash_final = hasher.result().as_slice().to_hex();
+// pick the pending transactions paying the best fee rates that fit in the given size of the block at the given height
+// locked transactions are checked against the median time past of the block's parent
+// account and UTXO transactions compete together: the best next transaction of any sender is compared
+// with the best UTXO transaction whose pending parents are already picked
+// a sender's transactions must follow its confirmed nonce and stay within its confirmed balance, as `validate_block_state` checks
+// returns the account and the UTXO transactions with the total of their fees
+fn select_transactions(
+    max_size: usize,
+    id: i32,
+    median_time_past: i64,
+    utxo_block: bool
+) -> Result<(Vec<Transaction>, Vec<UtxoTransaction>, Amount), CoreError> {
+    // the next transaction of every sender is the last of its queue
+    let mut queues: Vec<Vec<Transaction>> = mempool::get_sender_queues();
+    for queue in queues.iter_mut() {
+        queue.reverse();
     }
 
-    println!("FOUND {}", hash_final);
-    println!("WITH NONCE {}", nonce);
-
-    // get a Vec<u8> from the hash hex string
-    let hash_final: Vec<u8> = FromHex::from_hex(hash_final)?;
-
-    Ok((hash_final, nonce))
-}
-
-// get the root hash of every transaction's hash using a merkle tree
-fn get_merkle_root(hash_list: &Vec<Vec<u8>>) -> Vec<u8> {
-    let hash_list_len = hash_list.len();
-
-    if hash_list_len > 0 {
-        if hash_list_len == 1 {
-            // return the first element which is the merkle root
-            return hash_list[0].clone();
-        } else {
-            let mut hash_list_computed: Vec<Vec<u8>> = Vec::new();
-
-            // step in the hash list 2 by 2
-            // XXX use this when `step_by()` becomes stable
-            // for i in (0..hash_list_len - 1).step_by(2) {
-            //
-            // }
-
-            let mut i = 0;
-            while i < hash_list_len - 1 {
-                // hash (n, n+1) together and push the result hash in a new hash list
-                let mut hasher = Sha256::default();
-                hasher.input(&[&hash_list[i][..], &hash_list[i+1][..]].concat());
-                hash_list_computed.push(hasher.result().as_slice().to_vec());
-
-                i += 2;
-            }
-
-            if hash_list_len % 2 != 0 {
-                // if the hash list has an odd length, duplicate the last element to make it even
-                hash_list_computed.push(
-                    match hash_list.last() {
-                        Some(tx) => {
-                            let mut hasher = Sha256::default();
-                            hasher.input(&[&tx[..], &tx[..]].concat());
-                            hasher.result().as_slice().to_vec()
-                        },
-                        None => Vec::<u8>::new()
-                    }
-                );
-            }
-
-            // recall the function untill the hash list has a length of 1
-            get_merkle_root(&hash_list_computed)
-        }
+    // UTXO transactions only go in version 2 blocks
+    let mut candidates: Vec<(UtxoTransaction, Amount)> = if utxo_block {
+        utxo::get_pending()?.0
     } else {
-        return Vec::<u8>::new();
+        Vec::new()
+    };
+    candidates.sort_by_key(|&(ref tx, fee)| Reverse(transactions::get_fee_rate(fee, tx.get_size())));
+
+    // a UTXO transaction spending the outputs of a pending one can't be picked before it
+    let mut unpicked: HashSet<Vec<u8>> = candidates.iter().map(|&(ref tx, _)| tx.get_id()).collect();
+
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+    let mut view = UtxoView::new();
+
+    // amount debited and number of transactions of every sender so far in the block
+    let mut debited: HashMap<Vec<u8>, Amount> = HashMap::new();
+    let mut sent: HashMap<Vec<u8>, u64> = HashMap::new();
+
+    let mut selected: Vec<Transaction> = Vec::new();
+    let mut selected_utxo: Vec<UtxoTransaction> = Vec::new();
+    let mut size: usize = 0;
+    let mut fees: Amount = Amount::zero();
+
+    loop {
+        // the sender whose next transaction pays the best fee rate
+        let best: Option<(usize, u64)> = queues.iter()
+            .enumerate()
+            .filter_map(|(i, queue)| queue.last().map(|tx| (i, tx.get_fee_rate())))
+            .max_by_key(|&(_, fee_rate)| fee_rate);
+
+        // the candidates are sorted by fee rate, the first one whose parents are picked is the best
+        let best_utxo: Option<(usize, u64)> = candidates.iter()
+            .enumerate()
+            .find(|&(_, &(ref tx, _))| tx.inputs.iter().all(|input| !unpicked.contains(&input.previous_output.tx_id)))
+            .map(|(i, &(ref tx, fee))| (i, transactions::get_fee_rate(fee, tx.get_size())));
+
+        match (best, best_utxo) {
+            (Some((i, fee_rate)), best_utxo) if best_utxo.map_or(true, |(_, utxo_fee_rate)| fee_rate >= utxo_fee_rate) => {
+                let tx: Transaction = queues[i].pop().unwrap();
+                let sender: Vec<u8> = tx.transaction.content.sender_addr.clone();
+                let sender_addr: String = sender.to_base58();
+
+                let total: Option<Amount> = tx.get_debit()
+                    .and_then(|debit| debit.checked_add(*debited.get(&sender).unwrap_or(&Amount::zero())));
+                let affordable: bool = match total {
+                    Some(total) => state::get_balance(&*conn, &sender_addr)? >= total,
+                    None => false
+                };
+                let count: u64 = *sent.get(&sender).unwrap_or(&0);
+                let in_sequence: bool = tx.transaction.content.nonce == state::get_nonce(&*conn, &sender_addr)? + count;
+
+                if size + tx.get_size() > max_size || !tx.is_final(id, median_time_past) || !affordable || !in_sequence {
+                    // the following transactions of the sender can't be included without this one
+                    // (too large for the remaining size, still locked, unaffordable or out of sequence)
+                    queues[i].clear();
+                    continue;
+                }
+
+                // can't be none as it was checked above
+                debited.insert(sender.clone(), total.unwrap());
+                sent.insert(sender, count + 1);
+                size += tx.get_size();
+                fees = fees.checked_add(tx.get_fee()).ok_or(CoreError::AmountOverflow)?;
+                selected.push(tx);
+            },
+            (_, Some((i, _))) => {
+                let (tx, fee) = candidates.remove(i);
+
+                // a transaction left out stays unpicked, so the ones spending its outputs are left out too
+                if size + tx.get_size() > max_size || view.apply(&*conn, &tx).is_err() {
+                    continue;
+                }
+
+                unpicked.remove(&tx.get_id());
+                size += tx.get_size();
+                fees = fees.checked_add(fee).ok_or(CoreError::AmountOverflow)?;
+                selected_utxo.push(tx);
+            },
+            _ => break
+        }
     }
+
+    Ok((selected, selected_utxo, fees))
 }
 
 // verify a block
-pub fn verify(header: &Header, mined_hash: &Vec<u8>, nonce: i64) -> Result<bool, CoreError> {
-    // serialize the block header
-    let header_encoded: Vec<u8> = serialize(header, Infinite)?;
-
-    // hash the block header
-    let mut hasher = Sha256::default();
-    hasher.input(&header_encoded);
-    let header_hashed: Vec<u8> = hasher.result().as_slice().to_vec();
-
-    // FIXME working with strings... should concat bytes directly
-    let tested_payload = format!("{}{}", header_hashed.to_hex(), nonce);
+pub fn verify(header: &Header, mined_hash: &Vec<u8>) -> Result<bool, CoreError> {
+    // the difficulty committed in the header must be the one expected on top of its parent
+    if header.difficulty != difficulty::get_expected_difficulty(&header.previous_hash)? {
+        return Ok(false);
+    }
 
-    // hash the block header
-    hasher = Sha256::default();
-    hasher.input(&tested_payload.as_bytes());
-    let hash: Vec<u8> = hasher.result().as_slice().to_vec();
+    let hash: Vec<u8> = header.get_hash();
 
-    Ok(hash == *mined_hash)
+    Ok(hash == *mined_hash && difficulty::meets_difficulty(&hash, header.difficulty))
 }
@@ -0,0 +1,141 @@
+use blockchain;
+use blocks::HASH_SIZE;
+use errors::CoreError;
+use params;
+
+// number of blocks between two difficulty adjustments
+pub const RETARGET_INTERVAL: i32 = 10;
+// expected time between two blocks (ms)
+pub const TARGET_BLOCK_TIME: i64 = 60 * 1000;
+// bounds of the difficulty (number of leading zero bits in the block hash)
+// the upper bound keeps the cumulative chain work of a long chain within a u128
+pub const MIN_DIFFICULTY: u32 = 1;
+pub const MAX_DIFFICULTY: u32 = 96;
+// maximum number of bits the difficulty can move at each retarget
+pub const MAX_ADJUSTMENT: u32 = 2;
+
+// get the difficulty a block built on top of the given parent must meet
+// the difficulty is the number of leading zero bits required in the block hash
+pub fn get_expected_difficulty(previous_hash: &Vec<u8>) -> Result<u32, CoreError> {
+    // the genesis has no parent to read the difficulty from
+    if *previous_hash == vec![0; HASH_SIZE] {
+        return Ok(params::get().initial_difficulty);
+    }
+
+    let previous = match blockchain::get_header_by_hash(previous_hash)? {
+        Some(header) => header,
+        None => return Err(CoreError::DatabaseError)
+    };
+    let id = previous.id + 1;
+
+    // only retarget at the end of an interval, otherwise keep the parent's difficulty
+    if id % RETARGET_INTERVAL != 0 {
+        return Ok(previous.difficulty);
+    }
+
+    // time it took to mine the last interval on the parent's branch
+    let first = blockchain::get_ancestor(&previous, RETARGET_INTERVAL - 1)?;
+    let actual_time: i64 = previous.timestamp.saturating_sub(first.timestamp);
+    let expected_time: i64 = TARGET_BLOCK_TIME * (RETARGET_INTERVAL - 1) as i64;
+
+    Ok(retarget(previous.difficulty, actual_time, expected_time))
+}
+
+// compute the new difficulty from the observed and the expected timespan
+// every leading zero bit doubles the work, so we move one bit per factor of two
+fn retarget(difficulty: u32, actual_time: i64, expected_time: i64) -> u32 {
+    // a negative or null timespan means timestamps are off, consider blocks were too fast
+    let mut actual_time = if actual_time > 0 { actual_time } else { 1 };
+    let mut difficulty = difficulty;
+    let mut adjustment = 0;
+
+    // blocks came too fast, increase the difficulty
+    while actual_time.saturating_mul(2) <= expected_time && adjustment < MAX_ADJUSTMENT {
+        difficulty += 1;
+        actual_time = actual_time.saturating_mul(2);
+        adjustment += 1;
+    }
+
+    // blocks came too slow, decrease the difficulty
+    while actual_time >= expected_time.saturating_mul(2) && adjustment < MAX_ADJUSTMENT {
+        difficulty = difficulty.saturating_sub(1);
+        actual_time /= 2;
+        adjustment += 1;
+    }
+
+    if difficulty < MIN_DIFFICULTY {
+        MIN_DIFFICULTY
+    } else if difficulty > MAX_DIFFICULTY {
+        MAX_DIFFICULTY
+    } else {
+        difficulty
+    }
+}
+
+// count the leading zero bits of a hash
+pub fn get_leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros: u32 = 0;
+
+    for byte in hash {
+        if *byte == 0 {
+            zeros += 8;
+        } else {
+            zeros += byte.leading_zeros();
+            break;
+        }
+    }
+
+    zeros
+}
+
+// check if a hash satisfies the given difficulty
+pub fn meets_difficulty(hash: &[u8], difficulty: u32) -> bool {
+    get_leading_zeros(hash) >= difficulty
+}
+
+// get the expected number of hashes needed to mine a block at the given difficulty
+pub fn get_block_work(difficulty: u32) -> u128 {
+    1u128 << difficulty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED_TIME: i64 = TARGET_BLOCK_TIME * (RETARGET_INTERVAL - 1) as i64;
+
+    #[test]
+    fn retarget_moves_one_bit_per_factor_of_two() {
+        assert_eq!(retarget(10, EXPECTED_TIME, EXPECTED_TIME), 10);
+        assert_eq!(retarget(10, EXPECTED_TIME / 2, EXPECTED_TIME), 11);
+        assert_eq!(retarget(10, EXPECTED_TIME * 2, EXPECTED_TIME), 9);
+        // less than a factor of two keeps the difficulty
+        assert_eq!(retarget(10, EXPECTED_TIME * 3 / 2, EXPECTED_TIME), 10);
+    }
+
+    #[test]
+    fn retarget_is_clamped_at_the_max_adjustment() {
+        assert_eq!(retarget(10, 1, EXPECTED_TIME), 10 + MAX_ADJUSTMENT);
+        assert_eq!(retarget(10, EXPECTED_TIME * 1000, EXPECTED_TIME), 10 - MAX_ADJUSTMENT);
+        // timestamps going backwards count as blocks too fast
+        assert_eq!(retarget(10, -EXPECTED_TIME, EXPECTED_TIME), 10 + MAX_ADJUSTMENT);
+        assert_eq!(retarget(10, i64::max_value(), EXPECTED_TIME), 10 - MAX_ADJUSTMENT);
+    }
+
+    #[test]
+    fn retarget_stays_within_the_difficulty_bounds() {
+        assert_eq!(retarget(MIN_DIFFICULTY, EXPECTED_TIME * 1000, EXPECTED_TIME), MIN_DIFFICULTY);
+        assert_eq!(retarget(0, EXPECTED_TIME, EXPECTED_TIME), MIN_DIFFICULTY);
+        assert_eq!(retarget(MAX_DIFFICULTY, 1, EXPECTED_TIME), MAX_DIFFICULTY);
+        assert_eq!(retarget(MAX_DIFFICULTY - 1, 1, EXPECTED_TIME), MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn leading_zeros_decide_the_difficulty_met() {
+        let hash: Vec<u8> = vec![0, 0, 0x1f, 0xff];
+
+        assert_eq!(get_leading_zeros(&hash), 19);
+        assert!(meets_difficulty(&hash, 19));
+        assert!(!meets_difficulty(&hash, 20));
+    }
+}
@@ -19,7 +19,9 @@ pub enum CoreError {
     DatabaseError,
     SerializeError,
     CryptoError,
-    WalletError
+    WalletError,
+    ChainMismatch, // the database belongs to another network or chain
+    AmountOverflow // an amount went over the largest amount
 }
 
 impl From<StdError> for CoreError {
@@ -76,6 +78,50 @@ impl From<PostgresError> for CoreError {
     }
 }
 
+// reasons for rejecting a block
+#[derive(Debug)]
+pub enum BlockError {
+    CoreError,
+
+    UnknownParent,
+    InvalidHeight,
+    InvalidTimestamp,
+    InvalidVersion,
+    InvalidProofOfWork,
+    InvalidMerkleRoot,
+    BlockTooLarge,
+    MissingCoinbase,
+    MisplacedCoinbase,
+    InvalidCoinbaseAmount,
+    InvalidCoinbaseHeight, // a version 2 UTXO coinbase must commit to the height of its block
+    InvalidSignature(String), // id of the invalid transaction
+    InvalidTransactionId(String), // id a transaction was received with, which isn't derived from it
+    DuplicateTransaction(String), // id of the duplicated transaction
+    InsufficientFunds(String), // id of the transaction spending more than its sender has
+    InvalidNonce(String), // id of the transaction out of its sender's sequence
+    MissingInput(String), // id of the transaction spending an unknown or already spent output
+    ImmatureTransaction(String), // id of the transaction whose lock time hasn't passed
+    AmountOverflow(String) // id of the transaction making the amounts of the block overflow
+}
+
+impl From<CoreError> for BlockError {
+    fn from(_: CoreError) -> BlockError {
+        BlockError::CoreError
+    }
+}
+
+impl From<PostgresError> for BlockError {
+    fn from(_: PostgresError) -> BlockError {
+        BlockError::CoreError
+    }
+}
+
+impl From<FromHexError> for BlockError {
+    fn from(_: FromHexError) -> BlockError {
+        BlockError::CoreError
+    }
+}
+
 #[derive(Debug)]
 pub enum ServerError {
     CoreError,
@@ -83,8 +129,16 @@ pub enum ServerError {
     SerializeError,
 
     NotFound,
+    Forbidden, // a local route requested by another machine
+    InvalidParameter,
     InvalidTransaction,
-    InvalidBlock
+    DuplicateTransaction,
+    InsufficientFunds,
+    InvalidNonce,
+    InsufficientFee,
+    MissingInput,
+    MempoolFull,
+    InvalidBlock(BlockError)
 }
 
 impl From<CoreError> for ServerError {
@@ -93,6 +147,15 @@ impl From<CoreError> for ServerError {
     }
 }
 
+impl From<BlockError> for ServerError {
+    fn from(e: BlockError) -> ServerError {
+        match e {
+            BlockError::CoreError => ServerError::CoreError,
+            _ => ServerError::InvalidBlock(e)
+        }
+    }
+}
+
 impl From<FromBase58Error> for ServerError {
     fn from(_: FromBase58Error) -> ServerError {
         ServerError::SerializeError
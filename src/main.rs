@@ -7,6 +7,7 @@ extern crate serde;
 extern crate bincode;
 extern crate time;
 extern crate secp256k1;
+extern crate k256;
 extern crate base58;
 extern crate sha2;
 extern crate rusqlite;
@@ -19,15 +20,48 @@ extern crate postgres_array;
 #[macro_use] extern crate rouille;
 extern crate rustc_serialize;
 extern crate jfs;
+extern crate num_cpus;
+#[macro_use] extern crate lazy_static;
 
+mod amount;
 mod blockchain;
 mod blocks;
+mod difficulty;
 mod errors;
+mod mempool;
+mod merkle;
+mod miner;
+mod monetary;
+mod multisig;
 mod net;
+mod orphans;
+mod params;
+mod signature;
+mod state;
+mod templates;
 mod transactions;
 mod utils;
+mod utxo;
+mod validation;
 mod wallet;
 
+use std::env;
+
 fn main() {
+    // select the network from the command line, main network by default
+    let mut network = params::Network::Main;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--testnet" => network = params::Network::Test,
+            "--regtest" => network = params::Network::Regtest,
+            // keep the pending transactions across restarts
+            "--persist-mempool" => mempool::enable_persistence("db/mempool.dat"),
+            _ => {}
+        }
+    }
+
+    params::select(network);
+
     net::server::start();
 }
@@ -0,0 +1,605 @@
+use std::collections::{HashMap, BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use bincode::{serialize, deserialize, Infinite};
+use base58::ToBase58;
+
+use amount::Amount;
+use transactions::{self, Transaction};
+use utxo::UtxoTransaction;
+use errors::{CoreError, ServerError};
+use blockchain;
+use miner;
+use state;
+use utils;
+
+// transactions waiting to be included in a block, kept in memory
+// account transactions are indexed by sender and nonce so that every sender's transactions stay in sequence,
+// UTXO transactions are kept in the order they were received as they can spend each other's outputs
+
+// limits of the mempool, the transactions paying the lowest fee rate are evicted beyond them
+pub const MAX_MEMPOOL_TRANSACTIONS: usize = 5000;
+pub const MAX_MEMPOOL_SIZE: usize = 5000000;
+// transactions still not included in a block this long (ms) after they became mature are dropped
+pub const MEMPOOL_EXPIRY: i64 = 3 * 24 * 3600 * 1000;
+// delay between two saves of the mempool when it is persisted (s)
+const PERSIST_INTERVAL: u64 = 60;
+// number of dropped transactions whose fate is remembered
+const MAX_DROPPED: usize = 10000;
+
+// what happened to a transaction accepted by the mempool, if it isn't in a block
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Pending,
+    Evicted, // dropped for its fee rate when the mempool was full, or expired
+    Rejected // conflicts with a transaction of the main chain
+}
+
+#[derive(Clone)]
+pub enum PoolTransaction {
+    Account(Transaction),
+    Utxo(UtxoTransaction)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    tx: Transaction,
+    received: i64,
+    matured: Option<i64>, // when its lock time passed, none while it is held
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct UtxoEntry {
+    tx: UtxoTransaction,
+    fee: Amount, // depends on the spent outputs, computed when the transaction is accepted
+    received: i64,
+    sequence: u64,
+}
+
+struct Mempool {
+    transactions: HashMap<Vec<u8>, Entry>,
+    // ids of every sender's transactions by nonce
+    senders: HashMap<Vec<u8>, BTreeMap<u64, Vec<u8>>>,
+    utxo_transactions: HashMap<Vec<u8>, UtxoEntry>,
+    // reception order of the UTXO transactions
+    sequence: u64,
+    // size of every serialized transaction
+    size: usize,
+    // changed since it was last saved
+    dirty: bool,
+    // height of the main chain's tip, which decides with the time if a locked transaction is mature
+    height: i32,
+    // transactions dropped without being included in a block, the oldest ones being forgotten first
+    dropped: HashMap<Vec<u8>, (Status, PoolTransaction)>,
+    dropped_order: VecDeque<Vec<u8>>,
+}
+
+lazy_static! {
+    static ref MEMPOOL: Mutex<Mempool> = Mutex::new(Mempool::new());
+    static ref PERSIST_PATH: Mutex<Option<String>> = Mutex::new(None);
+}
+
+impl Mempool {
+    fn new() -> Mempool {
+        Mempool {
+            transactions: HashMap::new(),
+            senders: HashMap::new(),
+            utxo_transactions: HashMap::new(),
+            sequence: 0,
+            size: 0,
+            dirty: false,
+            height: 0,
+            dropped: HashMap::new(),
+            dropped_order: VecDeque::new()
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.transactions.len() + self.utxo_transactions.len()
+    }
+
+    fn contains(&self, id: &Vec<u8>) -> bool {
+        self.transactions.contains_key(id) || self.utxo_transactions.contains_key(id)
+    }
+
+    fn insert(&mut self, entry: Entry) -> bool {
+        let mut entry = entry;
+        let id: Vec<u8> = entry.tx.id.clone();
+        let sender: Vec<u8> = entry.tx.transaction.content.sender_addr.clone();
+        let nonce: u64 = entry.tx.transaction.content.nonce;
+
+        // another transaction of the sender already uses this nonce
+        let nonce_used: bool = self.senders.get(&sender).map_or(false, |nonces| nonces.contains_key(&nonce));
+        if self.contains(&id) || nonce_used {
+            return false;
+        }
+
+        if entry.matured.is_none() && entry.tx.is_final(self.height + 1, entry.received) {
+            entry.matured = Some(entry.received);
+        }
+
+        self.dropped.remove(&id);
+        self.size += entry.tx.get_size();
+        self.senders.entry(sender).or_insert(BTreeMap::new()).insert(nonce, id.clone());
+        self.transactions.insert(id, entry);
+        self.dirty = true;
+
+        true
+    }
+
+    fn insert_utxo(&mut self, tx: UtxoTransaction, fee: Amount, received: i64) -> bool {
+        let id: Vec<u8> = tx.get_id();
+
+        if self.contains(&id) {
+            return false;
+        }
+
+        self.dropped.remove(&id);
+        self.size += tx.get_size();
+        self.sequence += 1;
+        self.utxo_transactions.insert(id, UtxoEntry {
+            tx: tx,
+            fee: fee,
+            received: received,
+            sequence: self.sequence
+        });
+        self.dirty = true;
+
+        true
+    }
+
+    fn remove_transaction(&mut self, id: &Vec<u8>) -> bool {
+        let entry: Entry = match self.transactions.remove(id) {
+            Some(entry) => entry,
+            None => return false
+        };
+
+        let sender = &entry.tx.transaction.content.sender_addr;
+        let empty: bool = match self.senders.get_mut(sender) {
+            Some(nonces) => {
+                nonces.remove(&entry.tx.transaction.content.nonce);
+                nonces.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.senders.remove(sender);
+        }
+
+        self.size -= entry.tx.get_size();
+        self.dirty = true;
+
+        true
+    }
+
+    // remove a transaction which won't be included in a block and remember why
+    fn discard(&mut self, id: &Vec<u8>, status: Status) {
+        let tx: PoolTransaction = if let Some(entry) = self.transactions.get(id) {
+            PoolTransaction::Account(entry.tx.clone())
+        } else if let Some(entry) = self.utxo_transactions.get(id) {
+            PoolTransaction::Utxo(entry.tx.clone())
+        } else {
+            return;
+        };
+
+        if !self.remove_transaction(id) {
+            self.remove_utxo(id);
+        }
+
+        self.dropped.insert(id.clone(), (status, tx));
+        self.dropped_order.push_back(id.clone());
+
+        while self.dropped_order.len() > MAX_DROPPED {
+            if let Some(oldest) = self.dropped_order.pop_front() {
+                self.dropped.remove(&oldest);
+            }
+        }
+    }
+
+    // discard the transactions of a sender from the given nonce on
+    // (the following transactions can't be included without the previous ones)
+    fn remove_from_nonce(&mut self, sender: &Vec<u8>, nonce: u64, status: Status) {
+        let ids: Vec<Vec<u8>> = match self.senders.get(sender) {
+            Some(nonces) => nonces.range(nonce..).map(|(_, id)| id.clone()).collect(),
+            None => return
+        };
+
+        for id in ids {
+            self.discard(&id, status);
+        }
+    }
+
+    fn remove_utxo(&mut self, id: &Vec<u8>) -> bool {
+        match self.utxo_transactions.remove(id) {
+            Some(entry) => {
+                self.size -= entry.tx.get_size();
+                self.dirty = true;
+                true
+            },
+            None => false
+        }
+    }
+
+    // discard a UTXO transaction and the transactions spending its outputs
+    fn remove_utxo_with_descendants(&mut self, id: &Vec<u8>, status: Status) {
+        if !self.utxo_transactions.contains_key(id) {
+            return;
+        }
+        self.discard(id, status);
+
+        let children: Vec<Vec<u8>> = self.utxo_transactions.iter()
+            .filter(|&(_, entry)| entry.tx.inputs.iter().any(|input| input.previous_output.tx_id == *id))
+            .map(|(child, _)| child.clone())
+            .collect();
+
+        for child in children {
+            self.remove_utxo_with_descendants(&child, status);
+        }
+    }
+
+    // drop the transactions which became mature before the expiry delay
+    // held transactions don't expire, their expiry delay starts once their lock time passed
+    fn expire(&mut self, now: i64) {
+        let height: i32 = self.height;
+        for entry in self.transactions.values_mut() {
+            if entry.matured.is_none() && entry.tx.is_final(height + 1, now) {
+                entry.matured = Some(now);
+            }
+        }
+
+        let expired: Vec<(Vec<u8>, u64)> = self.transactions.values()
+            .filter(|entry| entry.matured.map_or(false, |matured| now - matured > MEMPOOL_EXPIRY))
+            .map(|entry| (entry.tx.transaction.content.sender_addr.clone(), entry.tx.transaction.content.nonce))
+            .collect();
+
+        for (sender, nonce) in expired {
+            self.remove_from_nonce(&sender, nonce, Status::Evicted);
+        }
+
+        let expired_utxo: Vec<Vec<u8>> = self.utxo_transactions.iter()
+            .filter(|&(_, entry)| now - entry.received > MEMPOOL_EXPIRY)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in expired_utxo {
+            self.remove_utxo_with_descendants(&id, Status::Evicted);
+        }
+    }
+
+    // evict the transactions paying the lowest fee rate until the mempool is within its limits
+    // only the last transaction of a sender can be evicted, so that the remaining ones stay in sequence
+    fn evict(&mut self) {
+        while self.count() > MAX_MEMPOOL_TRANSACTIONS || self.size > MAX_MEMPOOL_SIZE {
+            let lowest_tx: Option<(Vec<u8>, u64)> = self.senders.values()
+                .filter_map(|nonces| nonces.values().next_back())
+                .map(|id| (id.clone(), self.transactions[id].tx.get_fee_rate()))
+                .min_by_key(|&(_, fee_rate)| fee_rate);
+
+            let lowest_utxo: Option<(Vec<u8>, u64)> = self.utxo_transactions.iter()
+                .map(|(id, entry)| (id.clone(), transactions::get_fee_rate(entry.fee, entry.tx.get_size())))
+                .min_by_key(|&(_, fee_rate)| fee_rate);
+
+            match (lowest_tx, lowest_utxo) {
+                (Some((id, fee_rate)), Some((utxo_id, utxo_fee_rate))) => {
+                    if fee_rate <= utxo_fee_rate {
+                        self.discard(&id, Status::Evicted);
+                    } else {
+                        self.remove_utxo_with_descendants(&utxo_id, Status::Evicted);
+                    }
+                },
+                (Some((id, _)), None) => {
+                    self.discard(&id, Status::Evicted);
+                },
+                (None, Some((utxo_id, _))) => {
+                    self.remove_utxo_with_descendants(&utxo_id, Status::Evicted);
+                },
+                (None, None) => break
+            }
+        }
+    }
+}
+
+// add an account transaction to the mempool
+// returns false if it is already known, if its nonce is already used by another pending transaction
+// or if its fee rate is too low to stay in the full mempool
+pub fn add(tx: Transaction) -> bool {
+    let mut mempool = MEMPOOL.lock().unwrap();
+    let now: i64 = utils::get_current_timestamp();
+
+    mempool.expire(now);
+
+    add_locked(&mut mempool, tx, now)
+}
+
+// add a transaction received by the node to the mempool, if its sender can afford it on top of its pending transactions
+// and its nonce follows the last pending one, `balance` and `nonce` being the sender's confirmed ones
+// the checks and the insertion are done under the mempool's lock, so two transactions of a sender received
+// at the same time can't both pass them
+pub fn admit(tx: Transaction, balance: Amount, nonce: u64) -> Result<(), ServerError> {
+    let mut mempool = MEMPOOL.lock().unwrap();
+    let now: i64 = utils::get_current_timestamp();
+
+    mempool.expire(now);
+
+    if mempool.contains(&tx.id) {
+        return Err(ServerError::DuplicateTransaction);
+    }
+
+    // what the sender already spends in pending transactions, and how many it sent
+    let mut spending: Amount = Amount::zero();
+    let mut count: u64 = 0;
+    if let Some(nonces) = mempool.senders.get(&tx.transaction.content.sender_addr) {
+        for id in nonces.values() {
+            let debit: Amount = mempool.transactions[id].tx.get_debit().ok_or(ServerError::CoreError)?;
+            spending = spending.checked_add(debit).ok_or(ServerError::CoreError)?;
+            count += 1;
+        }
+    }
+
+    match tx.get_debit() {
+        Some(debit) if debit <= balance.saturating_sub(spending) => (),
+        _ => return Err(ServerError::InsufficientFunds)
+    }
+
+    if tx.transaction.content.nonce != nonce + count {
+        return Err(ServerError::InvalidNonce);
+    }
+
+    if !add_locked(&mut mempool, tx, now) {
+        return Err(ServerError::MempoolFull);
+    }
+
+    Ok(())
+}
+
+// insert an account transaction while holding the mempool's lock and make room for it
+// returns false if it wasn't inserted or was evicted right away
+fn add_locked(mempool: &mut Mempool, tx: Transaction, now: i64) -> bool {
+    let id: Vec<u8> = tx.id.clone();
+
+    if !mempool.insert(Entry { tx: tx, received: now, matured: None }) {
+        return false;
+    }
+
+    mempool.evict();
+
+    if !mempool.transactions.contains_key(&id) {
+        return false;
+    }
+
+    // the block template changed, abort the current mining attempt
+    miner::interrupt();
+
+    true
+}
+
+// add a UTXO transaction, whose fee has been computed against the UTXO set, to the mempool
+// returns false if it is already known or if its fee rate is too low to stay in the full mempool
+pub fn add_utxo(tx: UtxoTransaction, fee: Amount) -> bool {
+    let mut mempool = MEMPOOL.lock().unwrap();
+    let now: i64 = utils::get_current_timestamp();
+    let id: Vec<u8> = tx.get_id();
+
+    mempool.expire(now);
+
+    if !mempool.insert_utxo(tx, fee, now) {
+        return false;
+    }
+
+    mempool.evict();
+
+    if !mempool.utxo_transactions.contains_key(&id) {
+        return false;
+    }
+
+    // the block template changed, abort the current mining attempt
+    miner::interrupt();
+
+    true
+}
+
+// check if a transaction with the given id is pending
+pub fn contains(id: &Vec<u8>) -> bool {
+    MEMPOOL.lock().unwrap().contains(id)
+}
+
+// get a pending transaction, or a transaction dropped recently, with what happened to it
+pub fn get(id: &Vec<u8>) -> Option<(Status, PoolTransaction)> {
+    let mempool = MEMPOOL.lock().unwrap();
+
+    if let Some(entry) = mempool.transactions.get(id) {
+        return Some((Status::Pending, PoolTransaction::Account(entry.tx.clone())));
+    }
+    if let Some(entry) = mempool.utxo_transactions.get(id) {
+        return Some((Status::Pending, PoolTransaction::Utxo(entry.tx.clone())));
+    }
+
+    mempool.dropped.get(id).cloned()
+}
+
+// get the pending account transactions, every sender's transactions in nonce order
+pub fn get_transactions() -> Vec<Transaction> {
+    let mempool = MEMPOOL.lock().unwrap();
+
+    mempool.senders.values()
+        .flat_map(|nonces| nonces.values())
+        .map(|id| mempool.transactions[id].tx.clone())
+        .collect()
+}
+
+// get the pending UTXO transactions in the order they were received
+pub fn get_utxo_transactions() -> Vec<UtxoTransaction> {
+    let mempool = MEMPOOL.lock().unwrap();
+
+    let mut entries: Vec<&UtxoEntry> = mempool.utxo_transactions.values().collect();
+    entries.sort_by_key(|entry| entry.sequence);
+
+    entries.into_iter().map(|entry| entry.tx.clone()).collect()
+}
+
+// get the pending account transactions of every sender, in nonce order
+// the block template picks them by fee rate, a sender's next transaction competing once the previous one is picked
+pub fn get_sender_queues() -> Vec<Vec<Transaction>> {
+    let mut mempool = MEMPOOL.lock().unwrap();
+    mempool.expire(utils::get_current_timestamp());
+
+    mempool.senders.values()
+        .map(|nonces| nonces.values().map(|id| mempool.transactions[id].tx.clone()).collect())
+        .collect()
+}
+
+// remove the given transactions, included in a block, from the mempool
+pub fn remove(ids: &Vec<Vec<u8>>) {
+    let mut mempool = MEMPOOL.lock().unwrap();
+
+    for id in ids {
+        if !mempool.remove_transaction(id) {
+            mempool.remove_utxo(id);
+        }
+    }
+}
+
+// get the senders of the pending account transactions
+pub fn get_senders() -> Vec<Vec<u8>> {
+    MEMPOOL.lock().unwrap().senders.keys().cloned().collect()
+}
+
+// drop the pending transactions which don't follow the main chain's state anymore, once blocks are connected or disconnected
+// `confirmed` holds the balance and the nonce of senders as of the new tip, the other senders are left as they are
+// the transactions using nonces already used on the main chain are dropped, then every sender's remaining
+// transactions must start at its nonce and stay within its balance, the first one which doesn't and the following
+// ones are dropped
+pub fn revalidate(confirmed: &HashMap<Vec<u8>, (Amount, u64)>) {
+    let mut mempool = MEMPOOL.lock().unwrap();
+
+    for (sender, &(balance, nonce)) in confirmed {
+        let queue: Vec<(u64, Vec<u8>)> = match mempool.senders.get(sender) {
+            Some(nonces) => nonces.iter().map(|(tx_nonce, id)| (*tx_nonce, id.clone())).collect(),
+            None => continue
+        };
+
+        let mut next_nonce: u64 = nonce;
+        let mut spending: Amount = Amount::zero();
+
+        for (tx_nonce, id) in queue {
+            if tx_nonce < nonce {
+                mempool.discard(&id, Status::Rejected);
+                continue;
+            }
+
+            let total: Option<Amount> = mempool.transactions[&id].tx.get_debit()
+                .and_then(|debit| spending.checked_add(debit));
+
+            match total {
+                Some(total) if tx_nonce == next_nonce && total <= balance => {
+                    spending = total;
+                    next_nonce += 1;
+                },
+                _ => {
+                    mempool.remove_from_nonce(sender, tx_nonce, Status::Rejected);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// set the height of the main chain's tip, once blocks are connected or disconnected
+pub fn set_height(height: i32) {
+    MEMPOOL.lock().unwrap().height = height;
+}
+
+// save the mempool to the given file and restore it from there at startup
+pub fn enable_persistence(path: &str) {
+    *PERSIST_PATH.lock().unwrap() = Some(String::from(path));
+}
+
+// restore the persisted mempool and save it periodically from now on, if persistence is enabled
+// must be called once the chain state is loaded, as transactions already confirmed are dropped
+// the mempool is saved even if it couldn't be restored, the error is returned once saving started
+pub fn restore() -> Result<(), CoreError> {
+    let path: Option<String> = PERSIST_PATH.lock().unwrap().clone();
+
+    if let Some(ref path) = path {
+        let path: String = path.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(PERSIST_INTERVAL));
+
+                if let Err(e) = save(&path) {
+                    println!("MEMPOOL NOT SAVED {:?}", e);
+                }
+            }
+        });
+    }
+
+    set_height(blockchain::get_previous_id()?);
+
+    match path {
+        Some(path) => load(&path),
+        None => Ok(())
+    }
+}
+
+// add the transactions of the persisted mempool, if the file exists
+fn load(path: &String) -> Result<(), CoreError> {
+    if let Ok(mut file) = File::open(path) {
+        println!("RESTORE MEMPOOL");
+
+        let mut data: Vec<u8> = Vec::new();
+        file.read_to_end(&mut data)?;
+        let (entries, utxo_entries): (Vec<Entry>, Vec<UtxoEntry>) = deserialize(&data[..])?;
+
+        let mut mempool = MEMPOOL.lock().unwrap();
+        let now: i64 = utils::get_current_timestamp();
+
+        for entry in entries {
+            let content = &entry.tx.transaction.content;
+            if content.nonce >= state::get_confirmed_nonce(&content.sender_addr.to_base58())? {
+                mempool.insert(entry.clone());
+            }
+        }
+
+        // the UTXO transactions which don't apply anymore are skipped by the block templates until they expire
+        let mut utxo_entries = utxo_entries;
+        utxo_entries.sort_by_key(|entry| entry.sequence);
+        for entry in utxo_entries {
+            mempool.insert_utxo(entry.tx, entry.fee, entry.received);
+        }
+
+        mempool.expire(now);
+        mempool.evict();
+    }
+
+    Ok(())
+}
+
+// write the mempool to a file if it changed since the last save
+fn save(path: &String) -> Result<(), CoreError> {
+    let data: Vec<u8> = {
+        let mut mempool = MEMPOOL.lock().unwrap();
+        if !mempool.dirty {
+            return Ok(());
+        }
+        mempool.dirty = false;
+
+        let entries: Vec<Entry> = mempool.transactions.values().cloned().collect();
+        let utxo_entries: Vec<UtxoEntry> = mempool.utxo_transactions.values().cloned().collect();
+
+        serialize(&(entries, utxo_entries), Infinite)?
+    };
+
+    // write a temporary file and replace the previous one with it,
+    // so that a crash while writing never leaves a truncated file to restore
+    let tmp_path: String = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use sha2::{Sha256, Digest};
+
+// side on which a sibling hash is concatenated when climbing the tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Position {
+    Left,
+    Right
+}
+
+// one level of a merkle proof: the sibling of the current node
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofStep {
+    pub hash: Vec<u8>,
+    pub position: Position,
+}
+
+// get the root hash of every transaction's hash using a merkle tree
+// pairs are hashed together, the last element of an odd level is hashed with itself
+pub fn get_merkle_root(hash_list: &Vec<Vec<u8>>) -> Vec<u8> {
+    if hash_list.is_empty() {
+        return Vec::<u8>::new();
+    }
+
+    let mut level: Vec<Vec<u8>> = hash_list.clone();
+
+    // climb the tree until the level has a length of 1
+    while level.len() > 1 {
+        level = get_next_level(&level);
+    }
+
+    level.remove(0)
+}
+
+// build the proof that the hash at the given index is part of the tree
+// returns None if the index is out of the hash list
+pub fn get_proof(hash_list: &Vec<Vec<u8>>, index: usize) -> Option<Vec<ProofStep>> {
+    if index >= hash_list.len() {
+        return None;
+    }
+
+    let mut proof: Vec<ProofStep> = Vec::new();
+    let mut level: Vec<Vec<u8>> = hash_list.clone();
+    let mut index = index;
+
+    while level.len() > 1 {
+        let step = if index % 2 == 0 {
+            // the last element of an odd level is its own sibling
+            let sibling = if index + 1 < level.len() { &level[index + 1] } else { &level[index] };
+
+            ProofStep { hash: sibling.clone(), position: Position::Right }
+        } else {
+            ProofStep { hash: level[index - 1].clone(), position: Position::Left }
+        };
+
+        proof.push(step);
+        level = get_next_level(&level);
+        index /= 2;
+    }
+
+    Some(proof)
+}
+
+// check that a leaf hash and a proof lead to the given merkle root
+pub fn verify_proof(leaf: &Vec<u8>, proof: &Vec<ProofStep>, merkle_root: &Vec<u8>) -> bool {
+    let mut hash: Vec<u8> = leaf.clone();
+
+    for step in proof {
+        hash = match step.position {
+            Position::Left => hash_pair(&step.hash, &hash),
+            Position::Right => hash_pair(&hash, &step.hash)
+        };
+    }
+
+    hash == *merkle_root
+}
+
+// hash (n, n+1) together for every pair of a level
+fn get_next_level(level: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    level.chunks(2).map(|pair| {
+        match pair.len() {
+            2 => hash_pair(&pair[0], &pair[1]),
+            // if the level has an odd length, duplicate the last element to make it even
+            _ => hash_pair(&pair[0], &pair[0])
+        }
+    }).collect()
+}
+
+fn hash_pair(left: &Vec<u8>, right: &Vec<u8>) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(&[&left[..], &right[..]].concat());
+    hasher.result().as_slice().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_leaves(count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|i| {
+            let mut hasher = Sha256::default();
+            hasher.input(&[i as u8]);
+            hasher.result().as_slice().to_vec()
+        }).collect()
+    }
+
+    #[test]
+    fn proofs_lead_to_the_root() {
+        // odd and even leaf counts, including levels where the last leaf is duplicated
+        for count in 1..10 {
+            let leaves = get_leaves(count);
+            let root = get_merkle_root(&leaves);
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = get_proof(&leaves, index).unwrap();
+                assert!(verify_proof(leaf, &proof, &root), "leaf {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn proof_of_a_duplicated_leaf() {
+        let leaves = get_leaves(3);
+        let root = get_merkle_root(&leaves);
+        let proof = get_proof(&leaves, 2).unwrap();
+
+        // the last leaf of an odd level is its own sibling
+        assert_eq!(proof[0], ProofStep { hash: leaves[2].clone(), position: Position::Right });
+        assert_eq!(root, hash_pair(&hash_pair(&leaves[0], &leaves[1]), &hash_pair(&leaves[2], &leaves[2])));
+        assert!(verify_proof(&leaves[2], &proof, &root));
+    }
+
+    #[test]
+    fn proofs_reject_another_leaf_or_root() {
+        let leaves = get_leaves(4);
+        let root = get_merkle_root(&leaves);
+        let proof = get_proof(&leaves, 1).unwrap();
+
+        assert!(!verify_proof(&leaves[0], &proof, &root));
+        assert!(!verify_proof(&leaves[1], &proof, &get_merkle_root(&get_leaves(5))));
+        assert!(get_proof(&leaves, 4).is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use hex::ToHex;
+use num_cpus;
+
+use blocks::{self, Header};
+use difficulty;
+use errors::CoreError;
+use utils;
+use wallet;
+
+// number of nonces tried by a worker between two checks of the interruption flags
+const CHECK_INTERVAL: u64 = 1024;
+// most worker threads per core the local endpoints can ask for
+const MAX_THREADS_PER_CPU: usize = 4;
+
+// incremented every time the current mining attempt becomes obsolete
+// (a competing block was accepted or the block template changed)
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+// number of hashes computed during the current mining attempt
+static HASHES: AtomicUsize = AtomicUsize::new(0);
+
+// mining settings and statistics, controlled through the local mining endpoints
+#[derive(Clone)]
+pub struct MinerState {
+    pub running: bool,
+    pub reward_address: Option<Vec<u8>>,
+    pub threads: usize,
+    pub template_height: i32,
+    pub blocks_found: u32,
+    attempt_start: i64,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<MinerState> = Mutex::new(MinerState {
+        running: false,
+        reward_address: None,
+        threads: num_cpus::get(),
+        template_height: 0,
+        blocks_found: 0,
+        attempt_start: 0
+    });
+}
+
+// start mining blocks in the background until `stop` is called
+pub fn start() {
+    {
+        let mut state = STATE.lock().unwrap();
+        if state.running {
+            return;
+        }
+        state.running = true;
+    }
+
+    println!("START MINING");
+
+    thread::spawn(|| {
+        while STATE.lock().unwrap().running {
+            match blocks::new() {
+                Ok(true) => STATE.lock().unwrap().blocks_found += 1,
+                Ok(false) => {},
+                Err(e) => {
+                    // don't spin on a persistent error (no reward address, database down...)
+                    println!("MINING ERROR {:?}", e);
+                    STATE.lock().unwrap().running = false;
+                }
+            }
+        }
+
+        println!("MINING STOPPED");
+    });
+}
+
+// stop mining after aborting the current attempt
+pub fn stop() {
+    STATE.lock().unwrap().running = false;
+    interrupt();
+}
+
+// set the address receiving the coinbase of the mined blocks
+pub fn set_reward_address(address: Vec<u8>) {
+    STATE.lock().unwrap().reward_address = Some(address);
+    // the coinbase changes, so does the template
+    interrupt();
+}
+
+// get the address receiving the coinbase of the mined blocks, if one was set
+pub fn get_reward_address() -> Option<Vec<u8>> {
+    STATE.lock().unwrap().reward_address.clone()
+}
+
+// get the address to pay the reward to, falling back on the first local wallet
+pub fn get_payout_address() -> Result<Vec<u8>, CoreError> {
+    match get_reward_address() {
+        Some(address) => Ok(address),
+        None => wallet::get_default_address()
+    }
+}
+
+// get the most worker threads mining can use, every attempt spawns that many OS threads
+pub fn get_max_threads() -> usize {
+    num_cpus::get() * MAX_THREADS_PER_CPU
+}
+
+// set the number of worker threads, applied from the next mining attempt
+pub fn set_threads(threads: usize) {
+    STATE.lock().unwrap().threads = threads;
+    interrupt();
+}
+
+// get the hashrate (hashes per second) of the current mining attempt
+pub fn get_hashrate() -> f64 {
+    let state = STATE.lock().unwrap();
+    let elapsed = utils::get_current_timestamp() - state.attempt_start;
+
+    if !state.running || elapsed <= 0 {
+        return 0.0;
+    }
+
+    HASHES.load(Ordering::Relaxed) as f64 * 1000.0 / elapsed as f64
+}
+
+// get a copy of the current mining state
+pub fn get_state() -> MinerState {
+    STATE.lock().unwrap().clone()
+}
+
+// get the current mining epoch, to be captured before building a block template
+pub fn get_epoch() -> usize {
+    EPOCH.load(Ordering::SeqCst)
+}
+
+// abort the running mining attempt
+pub fn interrupt() {
+    println!("INTERRUPT MINING");
+    EPOCH.fetch_add(1, Ordering::SeqCst);
+}
+
+// search a nonce satisfying the header's difficulty using all cores
+// the nonce space is partitioned between workers: worker `i` tries `i`, `i + n`, `i + 2n`...
+// returns the solved header, or None if the attempt was interrupted or the nonce space is exhausted
+pub fn mine(header: &Header, epoch: usize) -> Option<Header> {
+    let workers = {
+        let mut state = STATE.lock().unwrap();
+        state.template_height = header.id;
+        state.attempt_start = utils::get_current_timestamp();
+        state.threads
+    };
+    HASHES.store(0, Ordering::Relaxed);
+
+    println!("PROOF OF WORK ON {} THREADS...", workers);
+
+    // serialize the header once, workers only rewrite the trailing nonce bytes
+    let header_encoded: Vec<u8> = header.to_bytes();
+    let difficulty: u32 = header.difficulty;
+
+    // set by the first worker finding a nonce to stop the others
+    let found = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+
+    let mut handles = Vec::new();
+
+    for i in 0..workers {
+        let mut header_encoded = header_encoded.clone();
+        let found = found.clone();
+        let sender = sender.clone();
+
+        handles.push(thread::spawn(move || {
+            let step = workers as u64;
+            let mut nonce = i as u64;
+            let nonce_offset = header_encoded.len() - 8;
+
+            loop {
+                // check from time to time if we should stop
+                if nonce / step % CHECK_INTERVAL == 0 {
+                    if found.load(Ordering::Relaxed) || get_epoch() != epoch {
+                        return;
+                    }
+                    HASHES.fetch_add(CHECK_INTERVAL as usize, Ordering::Relaxed);
+                }
+
+                // write the little-endian nonce at the end of the header
+                for j in 0..8 {
+                    header_encoded[nonce_offset + j] = (nonce >> (8 * j)) as u8;
+                }
+
+                let hash = blocks::get_header_hash(&header_encoded);
+
+                if difficulty::meets_difficulty(&hash, difficulty) {
+                    found.store(true, Ordering::Relaxed);
+                    // the receiver only keeps the first result, ignore send errors
+                    let _ = sender.send((hash, nonce));
+                    return;
+                }
+
+                // stop when the worker reaches the end of the nonce space
+                nonce = match nonce.checked_add(step) {
+                    Some(n) => n,
+                    None => return
+                };
+            }
+        }));
+    }
+
+    // drop our own sender so `recv` fails once every worker gave up
+    drop(sender);
+
+    let result = receiver.recv().ok();
+
+    // make sure every worker is stopped before returning
+    found.store(true, Ordering::Relaxed);
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    match result {
+        Some((hash, nonce)) => {
+            println!("FOUND {}", hash.to_hex());
+            println!("WITH NONCE {}", nonce);
+
+            // a competing block may have been accepted while we were finishing
+            if get_epoch() != epoch {
+                println!("MINING INTERRUPTED");
+                return None;
+            }
+
+            let mut header = header.clone();
+            header.nonce = nonce;
+
+            Some(header)
+        },
+        None => {
+            println!("MINING INTERRUPTED");
+            None
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use amount::Amount;
+use params;
+
+// the subsidy schedule (initial subsidy, halving interval and max supply) is defined per network in `params`
+
+// get the subsidy, without fees, of the block at the given height
+pub fn get_block_subsidy(id: i32) -> Amount {
+    if id <= 0 {
+        return Amount::zero();
+    }
+
+    // never create more than what remains to reach the max supply
+    let remaining: Amount = get_max_supply().saturating_sub(get_issued_supply(id - 1));
+    let subsidy: Amount = get_halved_subsidy(id);
+
+    if subsidy < remaining {
+        subsidy
+    } else {
+        remaining
+    }
+}
+
+// get the maximum amount a coinbase transaction can claim at the given height, none if it overflows
+pub fn get_max_coinbase_amount(id: i32, fees: Amount) -> Option<Amount> {
+    get_block_subsidy(id).checked_add(fees)
+}
+
+// get the amount no coinbase can make the supply go over
+pub fn get_max_supply() -> Amount {
+    // the params are hardcoded well below the largest amount
+    Amount::from_base_units(params::get().max_supply).unwrap()
+}
+
+// subsidy at the given height according to the halving schedule only
+fn get_halved_subsidy(id: i32) -> Amount {
+    let params = params::get();
+    let halvings = (id - 1) / params.halving_interval;
+
+    // the subsidy is null once it has been shifted out
+    if halvings >= 64 {
+        Amount::zero()
+    } else {
+        Amount::from_base_units(params.initial_subsidy >> halvings).unwrap()
+    }
+}
+
+// get the total subsidy created by the blocks up to the given height
+pub fn get_issued_supply(id: i32) -> Amount {
+    let params = params::get();
+    let max_supply: Amount = get_max_supply();
+    let mut issued: Amount = Amount::zero();
+    let mut start: i32 = 1;
+
+    // sum every halving era up to the given height
+    while start <= id {
+        let end: i32 = if id - start < params.halving_interval { id } else { start + params.halving_interval - 1 };
+        let subsidy = get_halved_subsidy(start);
+
+        if subsidy.is_zero() {
+            break;
+        }
+
+        // an era issuing more than the max supply ends the sum
+        let era: Option<Amount> = subsidy.to_base_units()
+            .checked_mul((end - start + 1) as u64)
+            .and_then(Amount::from_base_units)
+            .and_then(|era| issued.checked_add(era));
+
+        issued = match era {
+            Some(total) if total < max_supply => total,
+            _ => return max_supply
+        };
+        start = end + 1;
+    }
+
+    issued
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amount::{COIN, MAX_AMOUNT};
+
+    // the main network's schedule is selected by default: 50 coins halving every 100000 blocks up to 9500000 coins
+    // the cap is reached by the 40000th block of the fifth era, which pays 3.125 coins
+    const CAP_HEIGHT: i32 = 440000;
+
+    fn coins(units: u64) -> Amount {
+        Amount::from_base_units(units).unwrap()
+    }
+
+    #[test]
+    fn subsidy_halves_at_the_interval() {
+        assert_eq!(get_block_subsidy(0), Amount::zero());
+        assert_eq!(get_block_subsidy(1), coins(50 * COIN));
+        assert_eq!(get_block_subsidy(100000), coins(50 * COIN));
+        assert_eq!(get_block_subsidy(100001), coins(25 * COIN));
+        assert_eq!(get_block_subsidy(200001), coins(25 * COIN / 2));
+    }
+
+    #[test]
+    fn issued_supply_sums_the_eras() {
+        assert_eq!(get_issued_supply(0), Amount::zero());
+        assert_eq!(get_issued_supply(100000), coins(5000000 * COIN));
+        assert_eq!(get_issued_supply(100001), coins(5000025 * COIN));
+        assert_eq!(get_issued_supply(200000), coins(7500000 * COIN));
+    }
+
+    #[test]
+    fn subsidy_stops_at_the_max_supply() {
+        let last_subsidy: Amount = coins(3125 * COIN / 1000);
+
+        assert_eq!(get_issued_supply(CAP_HEIGHT - 1), get_max_supply().saturating_sub(last_subsidy));
+        assert_eq!(get_block_subsidy(CAP_HEIGHT), last_subsidy);
+        assert_eq!(get_issued_supply(CAP_HEIGHT), get_max_supply());
+        assert_eq!(get_block_subsidy(CAP_HEIGHT + 1), Amount::zero());
+        assert_eq!(get_issued_supply(i32::max_value()), get_max_supply());
+    }
+
+    #[test]
+    fn coinbase_collects_the_fees() {
+        let fees: Amount = coins(COIN);
+
+        assert_eq!(get_max_coinbase_amount(1, fees), Some(coins(51 * COIN)));
+        assert_eq!(get_max_coinbase_amount(CAP_HEIGHT + 1, fees), Some(fees));
+        assert_eq!(get_max_coinbase_amount(1, coins(MAX_AMOUNT)), None);
+    }
+}
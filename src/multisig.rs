@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use bincode::{serialize, deserialize, Infinite};
+use secp256k1;
+use secp256k1::key::PublicKey;
+
+use errors::CoreError;
+use signature::{self, SignatureScheme};
+use wallet;
+
+// a multisig address is the hash of a policy: a set of public keys and the number of them which must sign
+// transactions sent from it carry the serialized policy as `sender_pubkey`
+// and the serialized partial signatures as `signature`
+
+// maximum number of keys of a policy
+pub const MAX_MULTISIG_KEYS: usize = 16;
+// maximum size of a serialized partial signature (index, signature length and signature)
+pub const PARTIAL_SIGNATURE_SIZE: usize = 4 + 8 + signature::MAX_SIGNATURE_SIZE;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MultisigPolicy {
+    pub threshold: u32,
+    pub pubkeys: Vec<Vec<u8>>, // sorted, so that the same keys always give the same address
+}
+
+// signature of one of the policy's keys
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PartialSignature {
+    pub index: u32, // index of the key in the policy
+    pub signature: Vec<u8>,
+}
+
+impl MultisigPolicy {
+    // create the policy requiring `threshold` signatures of the given keys
+    pub fn new(threshold: u32, pubkeys: Vec<Vec<u8>>) -> Result<MultisigPolicy, CoreError> {
+        let mut pubkeys = pubkeys;
+        pubkeys.sort();
+
+        let policy = MultisigPolicy {
+            threshold: threshold,
+            pubkeys: pubkeys
+        };
+
+        if !policy.is_valid() {
+            return Err(CoreError::CryptoError);
+        }
+
+        // every key must be a valid public key
+        let secp = secp256k1::Secp256k1::new();
+        for pubkey in &policy.pubkeys {
+            PublicKey::from_slice(&secp, pubkey)?;
+        }
+
+        Ok(policy)
+    }
+
+    pub fn from_bytes(data: &Vec<u8>) -> Result<MultisigPolicy, CoreError> {
+        let policy: MultisigPolicy = deserialize(&data[..])?;
+        Ok(policy)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // can't fail as the size is unbounded
+        serialize(&self, Infinite).unwrap()
+    }
+
+    // hash the serialized policy to get the address
+    pub fn get_address(&self) -> Vec<u8> {
+        wallet::get_address(&self.to_bytes())
+    }
+
+    // check the threshold can be reached and the keys are distinct and sorted
+    pub fn is_valid(&self) -> bool {
+        let distinct: HashSet<&Vec<u8>> = self.pubkeys.iter().collect();
+
+        self.threshold >= 1
+            && self.threshold as usize <= self.pubkeys.len()
+            && self.pubkeys.len() <= MAX_MULTISIG_KEYS
+            && distinct.len() == self.pubkeys.len()
+            && self.pubkeys.windows(2).all(|keys| keys[0] < keys[1])
+    }
+
+    // check that at least `threshold` distinct keys of the policy signed the message with the given scheme
+    pub fn verify(
+        &self,
+        secp: &secp256k1::Secp256k1,
+        scheme: SignatureScheme,
+        message: &secp256k1::Message,
+        signatures: &Vec<PartialSignature>
+    ) -> Result<bool, CoreError> {
+        let mut signers: HashSet<u32> = HashSet::new();
+
+        for partial in signatures {
+            let pubkey = match self.pubkeys.get(partial.index as usize) {
+                Some(pubkey) => pubkey,
+                None => return Ok(false)
+            };
+
+            // a key can't sign twice
+            if !signers.insert(partial.index) {
+                return Ok(false);
+            }
+
+            if !signature::verify(secp, scheme, message, &partial.signature, pubkey) {
+                return Ok(false);
+            }
+        }
+
+        Ok(signers.len() >= self.threshold as usize)
+    }
+}
+
+// read the partial signatures of a multisig transaction, an empty signature has none yet
+pub fn decode_signatures(data: &Vec<u8>) -> Result<Vec<PartialSignature>, CoreError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let signatures: Vec<PartialSignature> = deserialize(&data[..])?;
+    Ok(signatures)
+}
+
+pub fn encode_signatures(signatures: &Vec<PartialSignature>) -> Vec<u8> {
+    // can't fail as the size is unbounded
+    serialize(signatures, Infinite).unwrap()
+}
@@ -1,80 +1,356 @@
-use std::thread;
-use hex::FromHex;
+use hex::{FromHex, ToHex};
+use base58::{FromBase58, ToBase58};
 use rouille::{input, Request, Response};
 
-use super::{nodes, NetTransaction, NetBlock};
-use errors::ServerError;
-use transactions;
+use super::{nodes, NetTransaction, NetBlock, NetBlockTemplate, NetMerkleProof, NetProofStep, NetBalance, NetNonce};
+use super::{NetUtxoTransaction, NetUnspentOutput, NetTransactionStatus, NetSubmittedTransaction};
+use errors::{ServerError, BlockError};
+use amount::Amount;
+use transactions::{self, Transaction};
+use utxo::{self, UtxoTransaction};
+use mempool;
 use blockchain;
 use blocks;
+use merkle;
+use miner;
+use state;
+use templates;
+use utils;
+use wallet;
 
 pub fn get_index(req: &Request) -> Result<Response, ServerError> {
     Ok(Response::text("Get /"))
 }
 
 pub fn post_transaction(req: &Request) -> Result<Response, ServerError> {
-    let tx_body: NetTransaction = input::json_input(req)?;
-
-    let tx = transactions::from(
-        &tx_body.id,
-        &tx_body.sender_addr,
-        &tx_body.sender_pubkey,
-        &tx_body.receiver_addr,
-        tx_body.amount,
-        tx_body.timestamp,
-        &tx_body.signature
-    )?;
-
-    // check if transaction is valid
-    // TODO check if sender is allowed to send that amount
-    // TODO check if transaction is not already on blockchain
-    if tx.is_valid()? {
-        // let nodes = nodes::get_nodes_from_server()?;
-        // nodes::save_nodes(&nodes)?;
-
-        blockchain::scan()?;
-
-        // send transaction to known nodes
-        // nodes::send_transaction(tx_body)?;
-
-        // save transaction in db
-        // tx.store_db()?;
-
-        // create a new block with the new transaction
-        // TODO use threads (safely)
-        // kill previous thread if we respawn one to recreate a block, otherwise the user will keep mining old block
-        // blocks::new()?;
+    let tx_body: NetSubmittedTransaction = input::json_input(req)?;
 
-        Ok(Response::text(""))
-    } else {
-        Err(ServerError::InvalidTransaction)
+    let tx = from_submitted(&tx_body)?;
+
+    // check if transaction is valid (coinbase transactions only exist in blocks)
+    if tx.is_coinbase() || !tx.is_valid()? {
+        return Err(ServerError::InvalidTransaction);
+    }
+
+    // check if transaction is not already on blockchain or waiting to be
+    let id: String = tx.id.to_hex();
+    if blockchain::contains_transaction(&id)? || mempool::contains(&tx.id) {
+        return Err(ServerError::DuplicateTransaction);
+    }
+
+    // relaying transactions that don't pay the minimum fee for their size would be free spam
+    if tx.get_fee() < transactions::get_min_relay_fee(tx.get_size()) {
+        return Err(ServerError::InsufficientFee);
     }
+
+    // a transaction locked too far ahead of the next block would never expire from the mempool
+    let height: i32 = blockchain::get_previous_id()? + 1;
+    if !tx.is_lock_time_within_reach(height, utils::get_current_timestamp()) {
+        return Err(ServerError::InvalidTransaction);
+    }
+
+    let sender_addr: String = tx.transaction.content.sender_addr.to_base58();
+    let balance: Amount = state::get_confirmed_balance(&sender_addr)?;
+    let nonce: u64 = state::get_confirmed_nonce(&sender_addr)?;
+
+    // let nodes = nodes::get_nodes_from_server()?;
+    // nodes::save_nodes(&nodes)?;
+
+    // send transaction to known nodes
+    // nodes::send_transaction(tx_body)?;
+
+    // keep the transaction until it is included in a block, if the sender is allowed to send that amount and fee
+    // on top of its pending transactions and the nonce follows its last transaction, pending ones included
+    // adding it interrupts the previous mining attempt as the template changed
+    mempool::admit(tx, balance, nonce)?;
+
+    // create a new block with the new transaction
+    // TODO use threads (safely)
+    // blocks::new()?;
+
+    Ok(Response::text(""))
+}
+
+// build a submitted transaction with the id derived from it
+// an id given along with the transaction must be that one, otherwise it could be relayed under any id
+fn from_submitted(tx_body: &NetSubmittedTransaction) -> Result<Transaction, ServerError> {
+    let tx = transactions::from_net(&tx_body.to_net())?;
+
+    if let Some(ref id) = tx_body.id {
+        if !tx.has_id(id) {
+            return Err(ServerError::InvalidTransaction);
+        }
+    }
+
+    Ok(tx)
+}
+
+pub fn post_utxo_transaction(req: &Request) -> Result<Response, ServerError> {
+    let tx_body: NetUtxoTransaction = input::json_input(req)?;
+
+    let tx = UtxoTransaction::from_raw(&tx_body.raw).map_err(|_| ServerError::InvalidTransaction)?;
+
+    accept_utxo_transaction(&tx)?;
+
+    Ok(Response::text(""))
+}
+
+// check a UTXO transaction against the main chain and the pending transactions, and add it to the mempool
+fn accept_utxo_transaction(tx: &UtxoTransaction) -> Result<(), ServerError> {
+    // coinbase transactions only exist in blocks
+    if tx.is_coinbase() || !tx.is_valid()? {
+        return Err(ServerError::InvalidTransaction);
+    }
+
+    if mempool::contains(&tx.get_id()) {
+        return Err(ServerError::DuplicateTransaction);
+    }
+
+    // the transaction can spend outputs of the main chain or of pending transactions, which aren't spent yet
+    let (_, mut view) = utxo::get_pending()?;
+
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    let fee: Amount = match view.apply(&*conn, tx) {
+        Ok(fee) => fee,
+        Err(BlockError::DuplicateTransaction(_)) => return Err(ServerError::DuplicateTransaction),
+        Err(BlockError::MissingInput(_)) => return Err(ServerError::MissingInput),
+        Err(BlockError::InsufficientFunds(_)) => return Err(ServerError::InsufficientFunds),
+        Err(BlockError::InvalidSignature(_)) => return Err(ServerError::InvalidTransaction),
+        Err(BlockError::AmountOverflow(_)) => return Err(ServerError::InvalidTransaction),
+        Err(_) => return Err(ServerError::CoreError)
+    };
+
+    if fee < transactions::get_min_relay_fee(tx.get_size()) {
+        return Err(ServerError::InsufficientFee);
+    }
+
+    if !mempool::add_utxo(tx.clone(), fee) {
+        return Err(ServerError::MempoolFull);
+    }
+
+    Ok(())
 }
 
 pub fn post_block(req: &Request) -> Result<Response, ServerError> {
     let block: NetBlock = input::json_input(req)?;
 
-    let block_header = blocks::Header::from(
-        block.id, block.timestamp, &block.previous_hash, &block.merkle_root
-    )?;
+    // validate the block, store it and switch to its branch if it has the most work
+    blockchain::process_block(block)?;
 
-    let mined_hash: Vec<u8> = FromHex::from_hex(&block.hash)?;
+    Ok(Response::text(""))
+}
 
-    let verified = blocks::verify(&block_header, &mined_hash, block.nonce)?;
+// get a transaction with what happened to it: included in a block of the main chain, pending in the mempool,
+// or dropped from it recently
+pub fn get_tx(req: &Request, id: String) -> Result<Response, ServerError> {
+    let id = id.to_lowercase();
+    let id_bytes: Vec<u8> = FromHex::from_hex(&id)?;
+
+    if let Some(block) = blockchain::get_block_by_tx_id(&id)? {
+        let transaction: Option<NetTransaction> = block.transactions.iter().find(|tx| tx.id == id).cloned();
+        let mut utxo_transaction: Option<String> = None;
+        if transaction.is_none() {
+            for raw in &block.utxo_transactions {
+                if UtxoTransaction::from_raw(raw)?.get_id() == id_bytes {
+                    utxo_transaction = Some(raw.clone());
+                }
+            }
+        }
+
+        let net_status = NetTransactionStatus {
+            id: id,
+            status: String::from("confirmed"),
+            transaction: transaction,
+            utxo_transaction: utxo_transaction,
+            confirmations: blockchain::get_previous_id()? - block.id + 1,
+            block_hash: Some(block.hash),
+            block_id: Some(block.id)
+        };
 
-    // TODO verify coinbase transaction
-    // (if it's first transaction in transactions vec + field verifications)
+        return Ok(Response::json(&net_status));
+    }
 
-    if verified {
-        // XXX is this safe?
-        thread::spawn(move || {
-            blockchain::add_block(block); // can't use ? here
-        });
+    let (status, tx) = match mempool::get(&id_bytes) {
+        Some(found) => found,
+        None => return Err(ServerError::NotFound)
+    };
+
+    let (transaction, utxo_transaction) = match tx {
+        mempool::PoolTransaction::Account(tx) => (Some(NetTransaction::from_transaction(&tx)), None),
+        mempool::PoolTransaction::Utxo(tx) => (None, Some(tx.to_raw()))
+    };
+
+    let net_status = NetTransactionStatus {
+        id: id,
+        status: String::from(match status {
+            mempool::Status::Pending => "pending",
+            mempool::Status::Evicted => "evicted",
+            mempool::Status::Rejected => "rejected"
+        }),
+        transaction: transaction,
+        utxo_transaction: utxo_transaction,
+        block_hash: None,
+        block_id: None,
+        confirmations: 0
+    };
+
+    Ok(Response::json(&net_status))
+}
 
-        Ok(Response::text(""))
-    } else {
-        Err(ServerError::InvalidBlock)
+// get the proof that a transaction is included in a block of the main chain
+pub fn get_tx_proof(req: &Request, id: String) -> Result<Response, ServerError> {
+    let id = id.to_lowercase();
+
+    let block = match blockchain::get_block_by_tx_id(&id)? {
+        Some(block) => block,
+        None => return Err(ServerError::NotFound)
+    };
+
+    // the merkle tree covers the account transactions followed by the UTXO transactions
+    // the stored ids are the ones the root committed to, ids derived again could differ for blocks stored
+    // before the transactions got their latest fields
+    let mut tx_hash_list: Vec<Vec<u8>> = Vec::new();
+    for tx in &block.transactions {
+        tx_hash_list.push(FromHex::from_hex(&tx.id)?);
+    }
+    for raw in &block.utxo_transactions {
+        tx_hash_list.push(UtxoTransaction::from_raw(raw)?.get_id());
     }
+
+    let id_bytes: Vec<u8> = FromHex::from_hex(&id)?;
+
+    let index = match tx_hash_list.iter().position(|tx_id| *tx_id == id_bytes) {
+        Some(index) => index,
+        None => return Err(ServerError::NotFound)
+    };
+
+    let proof = match merkle::get_proof(&tx_hash_list, index) {
+        Some(proof) => proof,
+        None => return Err(ServerError::NotFound)
+    };
+
+    // a proof which doesn't lead to the stored root would mislead the client, the stored block is inconsistent
+    let merkle_root: Vec<u8> = FromHex::from_hex(&block.merkle_root)?;
+    if !merkle::verify_proof(&id_bytes, &proof, &merkle_root) {
+        return Err(ServerError::CoreError);
+    }
+
+    let net_proof = NetMerkleProof {
+        tx_id: id,
+        block_hash: block.hash,
+        block_id: block.id,
+        merkle_root: block.merkle_root,
+        proof: proof.into_iter().map(|step| {
+            NetProofStep {
+                hash: step.hash.to_hex(),
+                position: match step.position {
+                    merkle::Position::Left => String::from("left"),
+                    merkle::Position::Right => String::from("right")
+                }
+            }
+        }).collect()
+    };
+
+    Ok(Response::json(&net_proof))
+}
+
+// get the confirmed balance of an address and the change pending in the mempool
+pub fn get_address_balance(req: &Request, address: String) -> Result<Response, ServerError> {
+    // check the address is valid base58
+    address.from_base58()?;
+
+    let net_balance = NetBalance {
+        confirmed: state::get_confirmed_balance(&address)?,
+        pending: state::get_pending_balance(&address)?,
+        address: address
+    };
+
+    Ok(Response::json(&net_balance))
+}
+
+// get the nonce the next transaction of an address must use, as of the main chain and with pending transactions
+pub fn get_address_nonce(req: &Request, address: String) -> Result<Response, ServerError> {
+    let address_bytes: Vec<u8> = address.from_base58()?;
+
+    let net_nonce = NetNonce {
+        confirmed: state::get_confirmed_nonce(&address)?,
+        pending: state::get_next_nonce(&address_bytes)?,
+        address: address
+    };
+
+    Ok(Response::json(&net_nonce))
+}
+
+// get the unspent outputs of an address as of the main chain
+pub fn get_address_utxos(req: &Request, address: String) -> Result<Response, ServerError> {
+    // check the address is valid base58
+    address.from_base58()?;
+
+    let net_outputs: Vec<NetUnspentOutput> = utxo::get_unspent_outputs(&address)?.into_iter()
+        .map(|(outpoint, output)| {
+            NetUnspentOutput {
+                tx_id: outpoint.tx_id.to_hex(),
+                index: outpoint.index,
+                amount: output.amount
+            }
+        })
+        .collect();
+
+    Ok(Response::json(&net_outputs))
+}
+
+// get a block template for an external miner, paying the given address or the configured one
+pub fn get_mining_template(req: &Request) -> Result<Response, ServerError> {
+    let reward_address: Vec<u8> = match req.get_param("address") {
+        Some(address) => address.from_base58()?,
+        None => miner::get_payout_address()?
+    };
+
+    if reward_address.len() != wallet::ADDRESS_SIZE {
+        return Err(ServerError::InvalidParameter);
+    }
+
+    let template = blocks::new_template(reward_address)?;
+    let net_block = NetBlock::from_block(&template);
+
+    let net_template = NetBlockTemplate {
+        template_id: templates::add(template.clone()),
+        version: net_block.version,
+        id: net_block.id,
+        timestamp: net_block.timestamp,
+        previous_hash: net_block.previous_hash,
+        merkle_root: net_block.merkle_root,
+        difficulty: net_block.difficulty,
+        header: template.header.to_bytes().to_hex(),
+        transactions: net_block.transactions,
+        utxo_transactions: net_block.utxo_transactions
+    };
+
+    Ok(Response::json(&net_template))
+}
+
+#[derive(Debug, RustcDecodable)]
+struct WorkSubmission {
+    template_id: String,
+    nonce: u64,
+}
+
+// submit the nonce solving a template previously handed out
+pub fn post_mining_submit(req: &Request) -> Result<Response, ServerError> {
+    let body: WorkSubmission = input::json_input(req)?;
+
+    let mut block = match templates::get(&body.template_id) {
+        Some(block) => block,
+        None => return Err(ServerError::NotFound)
+    };
+    block.header.nonce = body.nonce;
+
+    // the solved block goes through the same validation as any received block
+    blockchain::process_block(NetBlock::from_block(&block))?;
+
+    Ok(Response::text(""))
 }
 
 // local handlers (only accessible locally)
@@ -82,15 +358,21 @@ pub fn post_block(req: &Request) -> Result<Response, ServerError> {
 pub mod local {
     use std::ops::Index;
     use base58::{FromBase58, ToBase58};
-    use hex::ToHex;
+    use hex::{FromHex, ToHex};
     use rouille::{input, Request, Response};
     use jfs;
 
-    use super::nodes;
-    use net::{NetKeyPair, NetWallet};
+    use super::{nodes, accept_utxo_transaction, from_submitted};
+    use net::{NetKeyPair, NetWallet, NetMiningStatus, NetUtxoTransaction, NetPayment, NetTransaction, NetSubmittedTransaction, NetMultisigAddress};
     use errors::ServerError;
-    use transactions;
+    use amount::Amount;
+    use transactions::{self, Payment};
+    use multisig::{self, MultisigPolicy, PartialSignature};
+    use signature::SignatureScheme;
+    use utxo::{self, OutPoint, TxOutput};
     use wallet;
+    use miner;
+    use state;
 
     pub fn get_new_wallet(req: &Request) -> Result<Response, ServerError> {
         // TODO handle the fact that the user calls this by mistake (his previous wallet will be lost)
@@ -146,8 +428,12 @@ pub mod local {
     #[derive(Debug, RustcDecodable)]
     struct Transaction {
         sender_addr: String,
-        receiver_addr: String,
-        amount: i32,
+        receiver_addr: Option<String>,
+        amount: Option<Amount>,
+        recipients: Option<Vec<NetPayment>>, // pays every recipient in a single batch transaction
+        fee: Option<Amount>,
+        lock_time: Option<u64>, // block height, or timestamp in milliseconds, before which it can't be mined
+        data: Option<String>, // hex payload committed under the signature, such as an invoice reference
     }
 
     pub fn post_transaction(req: &Request) -> Result<Response, ServerError> {
@@ -156,16 +442,329 @@ pub mod local {
         // get wallet associated with given address from storage
         let wallet = wallet::get_wallet(&tx_body.sender_addr)?;
 
+        let (first, payments) = get_payments(tx_body.receiver_addr, tx_body.amount, tx_body.recipients)?;
+
         // deserialize addresses
         let sender_addr_bytes: Vec<u8> = tx_body.sender_addr.from_base58()?;
-        let receiver_addr_bytes: Vec<u8> = tx_body.receiver_addr.from_base58()?;
+
+        // use the nonce following the sender's last transaction
+        let nonce: u64 = state::get_next_nonce(&sender_addr_bytes)?;
 
         // create transaction for signature
-        let net_tx = transactions::new(wallet.keypair.private_key, wallet.keypair.public_key, sender_addr_bytes, receiver_addr_bytes, tx_body.amount)?;
+        let mut fee: Amount = tx_body.fee.unwrap_or(transactions::get_min_relay_fee(0));
+        let lock_time: u64 = tx_body.lock_time.unwrap_or(0);
+        let data: Vec<u8> = get_data(tx_body.data)?;
+        let mut net_tx = transactions::new(wallet.keypair.private_key, wallet.keypair.public_key.clone(), sender_addr_bytes.clone(), first.address.clone(), first.amount, payments.clone(), fee, nonce, lock_time, data.clone(), SignatureScheme::Bip340)?;
+
+        // without a given fee, pay the minimum relay fee of a batch spanning several kilobytes
+        let min_fee: Amount = transactions::get_min_relay_fee(transactions::from_net(&net_tx)?.get_size());
+        if tx_body.fee.is_none() && fee < min_fee {
+            fee = min_fee;
+            net_tx = transactions::new(wallet.keypair.private_key, wallet.keypair.public_key, sender_addr_bytes, first.address, first.amount, payments, fee, nonce, lock_time, data, SignatureScheme::Bip340)?;
+        }
 
         // broadcast transaction to network
         nodes::send_transaction(net_tx)?;
 
         Ok(Response::text(""))
     }
+
+    // the single receiver, if any, comes first, followed by the recipients of the batch
+    fn get_payments(
+        receiver_addr: Option<String>,
+        amount: Option<Amount>,
+        recipients: Option<Vec<NetPayment>>
+    ) -> Result<(Payment, Vec<Payment>), ServerError> {
+        let mut payments: Vec<Payment> = Vec::new();
+        if let (Some(receiver_addr), Some(amount)) = (receiver_addr, amount) {
+            payments.push(Payment { address: receiver_addr.from_base58()?, amount: amount });
+        }
+        for recipient in recipients.unwrap_or(Vec::new()) {
+            payments.push(Payment { address: recipient.address.from_base58()?, amount: recipient.amount });
+        }
+
+        if payments.is_empty()
+            || payments.len() > transactions::MAX_PAYMENTS + 1
+            || payments.iter().any(|payment| payment.amount.is_zero()) {
+            return Err(ServerError::InvalidParameter);
+        }
+        let first: Payment = payments.remove(0);
+
+        Ok((first, payments))
+    }
+
+    // decode the optional hex payload of a transaction
+    fn get_data(data: Option<String>) -> Result<Vec<u8>, ServerError> {
+        let data: Vec<u8> = match data {
+            Some(data) => FromHex::from_hex(&data)?,
+            None => Vec::new()
+        };
+
+        if data.len() > transactions::MAX_DATA_SIZE {
+            return Err(ServerError::InvalidParameter);
+        }
+
+        Ok(data)
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct MultisigAddress {
+        threshold: u32,
+        pubkeys: Vec<String>,
+    }
+
+    // create the address of the policy requiring `threshold` signatures of the given keys
+    // nothing is stored: the returned policy is needed to spend from the address
+    pub fn post_multisig_address(req: &Request) -> Result<Response, ServerError> {
+        let body: MultisigAddress = input::json_input(req)?;
+
+        let mut pubkeys: Vec<Vec<u8>> = Vec::new();
+        for pubkey in &body.pubkeys {
+            pubkeys.push(FromHex::from_hex(pubkey)?);
+        }
+
+        let policy = match MultisigPolicy::new(body.threshold, pubkeys) {
+            Ok(policy) => policy,
+            Err(_) => return Err(ServerError::InvalidParameter)
+        };
+
+        Ok(Response::json(&NetMultisigAddress {
+            address: policy.get_address().to_base58(),
+            policy: policy.to_bytes().to_hex()
+        }))
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct MultisigTransaction {
+        policy: String,
+        receiver_addr: Option<String>,
+        amount: Option<Amount>,
+        recipients: Option<Vec<NetPayment>>,
+        fee: Option<Amount>,
+        lock_time: Option<u64>,
+        data: Option<String>,
+    }
+
+    // create an unsigned transaction from a multisig address, to be passed around its signers
+    pub fn post_multisig_transaction(req: &Request) -> Result<Response, ServerError> {
+        let tx_body: MultisigTransaction = input::json_input(req)?;
+
+        let policy_bytes: Vec<u8> = FromHex::from_hex(&tx_body.policy)?;
+        let policy = match MultisigPolicy::from_bytes(&policy_bytes) {
+            Ok(ref policy) if policy.is_valid() => policy.clone(),
+            _ => return Err(ServerError::InvalidParameter)
+        };
+        let sender_addr_bytes: Vec<u8> = policy.get_address();
+
+        let (first, payments) = get_payments(tx_body.receiver_addr, tx_body.amount, tx_body.recipients)?;
+
+        // use the nonce following the sender's last transaction
+        let nonce: u64 = state::get_next_nonce(&sender_addr_bytes)?;
+
+        let mut fee: Amount = tx_body.fee.unwrap_or(transactions::get_min_relay_fee(0));
+        let lock_time: u64 = tx_body.lock_time.unwrap_or(0);
+        let data: Vec<u8> = get_data(tx_body.data)?;
+        let mut tx = transactions::new_unsigned(policy_bytes.clone(), sender_addr_bytes.clone(), first.address.clone(), first.amount, payments.clone(), fee, nonce, lock_time, data.clone(), SignatureScheme::Bip340)?;
+
+        // without a given fee, pay the minimum relay fee of the transaction once signed
+        let signed_size = tx.get_size() + policy.threshold as usize * multisig::PARTIAL_SIGNATURE_SIZE;
+        let min_fee: Amount = transactions::get_min_relay_fee(signed_size);
+        if tx_body.fee.is_none() && fee < min_fee {
+            fee = min_fee;
+            tx = transactions::new_unsigned(policy_bytes, sender_addr_bytes, first.address, first.amount, payments, fee, nonce, lock_time, data, SignatureScheme::Bip340)?;
+        }
+
+        Ok(Response::json(&NetTransaction::from_transaction(&tx)))
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct MultisigSign {
+        transaction: NetSubmittedTransaction,
+        address: String, // local wallet of one of the policy's keys
+    }
+
+    // add the signature of a local wallet to a multisig transaction
+    // the transaction is broadcast as soon as enough keys signed it
+    pub fn post_multisig_sign(req: &Request) -> Result<Response, ServerError> {
+        let body: MultisigSign = input::json_input(req)?;
+
+        let mut tx = from_submitted(&body.transaction)?;
+        let policy = match MultisigPolicy::from_bytes(&tx.transaction.content.sender_pubkey) {
+            Ok(ref policy) if policy.is_valid() => policy.clone(),
+            _ => return Err(ServerError::InvalidTransaction)
+        };
+
+        let wallet = wallet::get_wallet(&body.address)?;
+        let index = match policy.pubkeys.iter().position(|pubkey| *pubkey == wallet.keypair.public_key) {
+            Some(index) => index as u32,
+            None => return Err(ServerError::InvalidParameter)
+        };
+
+        let mut signatures: Vec<PartialSignature> = match multisig::decode_signatures(&tx.transaction.signature) {
+            Ok(signatures) => signatures,
+            Err(_) => return Err(ServerError::InvalidTransaction)
+        };
+
+        // signing twice replaces the previous signature of the key
+        signatures.retain(|partial| partial.index != index);
+        signatures.push(PartialSignature {
+            index: index,
+            signature: tx.transaction.content.get_signature(wallet.keypair.private_key)?
+        });
+
+        tx.transaction.signature = multisig::encode_signatures(&signatures);
+        tx.id = tx.transaction.get_id()?;
+
+        let net_tx = NetTransaction::from_transaction(&tx);
+
+        if signatures.len() >= policy.threshold as usize {
+            // broadcast transaction to network
+            nodes::send_transaction(net_tx.clone())?;
+        }
+
+        Ok(Response::json(&net_tx))
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct UtxoTransaction {
+        sender_addr: String,
+        receiver_addr: String,
+        amount: Amount,
+        fee: Option<Amount>,
+    }
+
+    // pay an address with unspent outputs of a local wallet, the rest goes back to the wallet as change
+    // without a given fee, the minimum relay fee for the size of the transaction is paid
+    pub fn post_utxo_transaction(req: &Request) -> Result<Response, ServerError> {
+        let tx_body: UtxoTransaction = input::json_input(req)?;
+
+        if tx_body.amount.is_zero() {
+            return Err(ServerError::InvalidParameter);
+        }
+
+        let wallet = wallet::get_wallet(&tx_body.sender_addr)?;
+        let receiver_addr_bytes: Vec<u8> = tx_body.receiver_addr.from_base58()?;
+
+        // skip the outputs already spent by pending transactions
+        let (_, view) = utxo::get_pending()?;
+        let unspent_outputs: Vec<(OutPoint, TxOutput)> = utxo::get_unspent_outputs(&tx_body.sender_addr)?.into_iter()
+            .filter(|&(ref outpoint, _)| !view.is_spent(outpoint))
+            .collect();
+
+        let mut fee: Amount = tx_body.fee.unwrap_or(transactions::get_min_relay_fee(0));
+
+        // the size, and so the minimum fee, grows with the number of inputs needed to cover the fee
+        loop {
+            let needed: Amount = match tx_body.amount.checked_add(fee) {
+                Some(needed) => needed,
+                None => return Err(ServerError::InvalidParameter)
+            };
+
+            let mut spent_outputs: Vec<OutPoint> = Vec::new();
+            let mut input_amount: Amount = Amount::zero();
+
+            for &(ref outpoint, ref output) in &unspent_outputs {
+                if input_amount >= needed {
+                    break;
+                }
+
+                // the outputs of an address can't add up to more than the supply
+                input_amount = input_amount.checked_add(output.amount).ok_or(ServerError::CoreError)?;
+                spent_outputs.push(outpoint.clone());
+            }
+
+            if input_amount < needed {
+                return Err(ServerError::InsufficientFunds);
+            }
+
+            let mut outputs: Vec<TxOutput> = vec![TxOutput { address: receiver_addr_bytes.clone(), amount: tx_body.amount }];
+            if input_amount > needed {
+                outputs.push(TxOutput { address: wallet.address.clone(), amount: input_amount.saturating_sub(needed) });
+            }
+
+            let tx = utxo::new(
+                wallet.keypair.private_key.clone(),
+                wallet.keypair.public_key.clone(),
+                SignatureScheme::Bip340,
+                spent_outputs,
+                outputs
+            )?;
+
+            let min_fee: Amount = transactions::get_min_relay_fee(tx.get_size());
+            if tx_body.fee.is_none() && fee < min_fee {
+                fee = min_fee;
+                continue;
+            }
+
+            accept_utxo_transaction(&tx)?;
+
+            // broadcast transaction to network
+            nodes::send_utxo_transaction(NetUtxoTransaction { raw: tx.to_raw() })?;
+
+            return Ok(Response::text(""));
+        }
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct MiningAddress {
+        address: String,
+    }
+
+    #[derive(Debug, RustcDecodable)]
+    struct MiningThreads {
+        threads: usize,
+    }
+
+    pub fn get_mining(req: &Request) -> Result<Response, ServerError> {
+        let state = miner::get_state();
+
+        let net_status = NetMiningStatus {
+            running: state.running,
+            reward_address: state.reward_address.map(|address| address.to_base58()),
+            threads: state.threads,
+            hashrate: miner::get_hashrate(),
+            template_height: state.template_height,
+            blocks_found: state.blocks_found
+        };
+
+        Ok(Response::json(&net_status))
+    }
+
+    pub fn post_mining_start(req: &Request) -> Result<Response, ServerError> {
+        miner::start();
+
+        Ok(Response::text(""))
+    }
+
+    pub fn post_mining_stop(req: &Request) -> Result<Response, ServerError> {
+        miner::stop();
+
+        Ok(Response::text(""))
+    }
+
+    pub fn post_mining_address(req: &Request) -> Result<Response, ServerError> {
+        let body: MiningAddress = input::json_input(req)?;
+
+        // any address can receive the reward, it doesn't have to be in the local wallet
+        let address: Vec<u8> = body.address.from_base58()?;
+        if address.len() != wallet::ADDRESS_SIZE {
+            return Err(ServerError::InvalidParameter);
+        }
+
+        miner::set_reward_address(address);
+
+        Ok(Response::text(""))
+    }
+
+    pub fn post_mining_threads(req: &Request) -> Result<Response, ServerError> {
+        let body: MiningThreads = input::json_input(req)?;
+
+        if body.threads == 0 || body.threads > miner::get_max_threads() {
+            return Err(ServerError::InvalidParameter);
+        }
+
+        miner::set_threads(body.threads);
+
+        Ok(Response::text(""))
+    }
 }
@@ -2,31 +2,74 @@ pub mod server;
 pub mod nodes;
 mod handlers;
 
+use std::iter;
 use hex::ToHex;
 use base58::ToBase58;
+use amount::Amount;
 use blocks::Block;
+use transactions::Transaction;
 
-#[derive(Serialize, Deserialize, RustcDecodable, FromSql, ToSql, Debug, Clone)]
+#[derive(Serialize, Deserialize, RustcDecodable, RustcEncodable, FromSql, ToSql, Debug, Clone)]
 #[postgres(name="tx")]
 pub struct NetTransaction {
     pub id: String,
     pub sender_addr: String,
     pub sender_pubkey: String,
     pub receiver_addr: String,
-    pub amount: i32, // u32
+    pub amount: Amount,
+    pub payments: Vec<NetPayment>,
+    pub fee: Amount,
+    pub nonce: i64, // u64
+    pub lock_time: i64, // u64
+    pub data: String, // Vec<u8>
+    pub timestamp: i64,
+    pub signature_scheme: i16, // u8
+    pub signature: String,
+}
+
+// transaction submitted by a client, its id is derived locally so it can be left out
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct NetSubmittedTransaction {
+    pub id: Option<String>,
+    pub sender_addr: String,
+    pub sender_pubkey: String,
+    pub receiver_addr: String,
+    pub amount: Amount,
+    pub payments: Vec<NetPayment>,
+    pub fee: Amount,
+    pub nonce: i64, // u64
+    pub lock_time: i64, // u64
+    pub data: String, // Vec<u8>
     pub timestamp: i64,
+    pub signature_scheme: i16, // u8
     pub signature: String,
 }
 
+// additional receiver of a batch payment
+#[derive(Serialize, Deserialize, RustcDecodable, RustcEncodable, FromSql, ToSql, Debug, Clone)]
+#[postgres(name="payment")]
+pub struct NetPayment {
+    pub address: String,
+    pub amount: Amount,
+}
+
 #[derive(Serialize, Deserialize, RustcDecodable, Debug, Clone)]
 pub struct NetBlock {
+    pub version: i32, // u32
     pub id: i32, // u32
     pub timestamp: i64,
     pub previous_hash: String,
     pub merkle_root: String, // Vec<u8>
+    pub difficulty: i32, // u32
     pub hash: String, // Vec<u8>
     pub nonce: i64, // u64
     pub transactions: Vec<NetTransaction>,
+    pub utxo_transactions: Vec<String>, // raw (hex serialized) UTXO transactions
+}
+
+#[derive(Serialize, Deserialize, RustcDecodable, Debug, Clone)]
+pub struct NetUtxoTransaction {
+    pub raw: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, RustcEncodable, Clone)]
@@ -41,55 +84,182 @@ pub struct NetWallet {
     pub address: String,
 }
 
+#[derive(RustcEncodable)]
+pub struct NetMultisigAddress {
+    pub address: String,
+    pub policy: String, // serialized policy, the `sender_pubkey` of transactions sent from the address
+}
+
+#[derive(RustcEncodable)]
+pub struct NetMiningStatus {
+    pub running: bool,
+    pub reward_address: Option<String>,
+    pub threads: usize,
+    pub hashrate: f64,
+    pub template_height: i32,
+    pub blocks_found: u32,
+}
+
+#[derive(RustcEncodable)]
+pub struct NetBalance {
+    pub address: String,
+    pub confirmed: Amount,
+    pub pending: i64, // change caused by unconfirmed transactions, in base units
+}
+
+#[derive(RustcEncodable)]
+pub struct NetNonce {
+    pub address: String,
+    pub confirmed: u64, // next nonce as of the main chain
+    pub pending: u64, // next nonce after the pending transactions
+}
+
+// block to be mined by an external miner
+// `header` is the serialized header, the nonce is its last 8 bytes (little-endian)
+#[derive(RustcEncodable)]
+pub struct NetBlockTemplate {
+    pub template_id: String,
+    pub version: i32, // u32
+    pub id: i32,
+    pub timestamp: i64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub difficulty: i32, // u32
+    pub header: String,
+    pub transactions: Vec<NetTransaction>,
+    pub utxo_transactions: Vec<String>,
+}
+
+#[derive(RustcEncodable)]
+pub struct NetUnspentOutput {
+    pub tx_id: String,
+    pub index: u32,
+    pub amount: Amount,
+}
+
+#[derive(RustcEncodable)]
+pub struct NetTransactionStatus {
+    pub id: String,
+    pub status: String, // "confirmed", "pending", "evicted" or "rejected"
+    pub transaction: Option<NetTransaction>, // for an account transaction
+    pub utxo_transaction: Option<String>, // raw, for a UTXO transaction
+    pub block_hash: Option<String>, // main chain's block including it
+    pub block_id: Option<i32>,
+    pub confirmations: i32,
+}
+
+#[derive(RustcEncodable)]
+pub struct NetProofStep {
+    pub hash: String,
+    pub position: String, // "left" or "right"
+}
+
+#[derive(RustcEncodable)]
+pub struct NetMerkleProof {
+    pub tx_id: String,
+    pub block_hash: String,
+    pub block_id: i32,
+    pub merkle_root: String,
+    pub proof: Vec<NetProofStep>,
+}
+
+impl NetTransaction {
+    // get the amount debited from the sender: every payment and the fee, none if it overflows
+    pub fn get_debit(&self) -> Option<Amount> {
+        let amounts = self.payments.iter().map(|payment| payment.amount);
+        Amount::checked_sum(iter::once(self.amount).chain(amounts).chain(iter::once(self.fee)))
+    }
+
+    pub fn from_transaction(tx: &Transaction) -> NetTransaction {
+        let id = tx.id.to_hex();
+        let receiver_addr = tx.transaction.content.receiver_addr.to_base58();
+
+        let sender_addr: String;
+        let sender_pubkey: String;
+        let signature: String;
+
+        // test if the transaction isn't coinbase
+        if tx.is_coinbase() {
+            sender_addr = String::from("0");
+            sender_pubkey = String::from("0");
+            signature = String::from("0");
+        } else {
+            sender_addr = tx.transaction.content.sender_addr.to_base58();
+            sender_pubkey = tx.transaction.content.sender_pubkey.to_hex();
+            signature = tx.transaction.signature.to_hex();
+        }
+
+        NetTransaction {
+            id: id,
+            sender_addr: sender_addr,
+            sender_pubkey: sender_pubkey,
+            receiver_addr: receiver_addr,
+            amount: tx.transaction.content.amount,
+            payments: tx.transaction.content.payments.iter().map(|payment| {
+                NetPayment {
+                    address: payment.address.to_base58(),
+                    amount: payment.amount
+                }
+            }).collect(),
+            fee: tx.transaction.content.fee,
+            nonce: tx.transaction.content.nonce as i64,
+            lock_time: tx.transaction.content.lock_time as i64,
+            data: tx.transaction.content.data.to_hex(),
+            timestamp: tx.transaction.content.timestamp,
+            signature_scheme: tx.transaction.content.signature_scheme as i16,
+            signature: signature
+        }
+    }
+}
+
+impl NetSubmittedTransaction {
+    // the submitted id is kept as is, `transactions::from_net` derives the real one
+    pub fn to_net(&self) -> NetTransaction {
+        NetTransaction {
+            id: self.id.clone().unwrap_or(String::new()),
+            sender_addr: self.sender_addr.clone(),
+            sender_pubkey: self.sender_pubkey.clone(),
+            receiver_addr: self.receiver_addr.clone(),
+            amount: self.amount,
+            payments: self.payments.clone(),
+            fee: self.fee,
+            nonce: self.nonce,
+            lock_time: self.lock_time,
+            data: self.data.clone(),
+            timestamp: self.timestamp,
+            signature_scheme: self.signature_scheme,
+            signature: self.signature.clone()
+        }
+    }
+}
+
 impl NetBlock {
-    pub fn from_block(block: Block) -> NetBlock {
+    pub fn from_block(block: &Block) -> NetBlock {
         let previous_hash = block.header.previous_hash.to_hex();
         let merkle_root = block.header.merkle_root.to_hex();
-        let hash = block.hash.to_hex();
+        let hash = block.header.get_hash().to_hex();
 
         // XXX converting every Transaction to NetTransaction seem to be overkill
         // maybe find a better solution that requires less iterations?
-        let net_txs: Vec<NetTransaction> = block.transactions.into_iter().map(|tx| {
-            let id = tx.id.to_hex();
-            let receiver_addr = tx.transaction.content.receiver_addr.to_base58();
-
-            let sender_addr: String;
-            let sender_pubkey: String;
-            let signature: String;
-
-            // test if the transaction isn't coinbase
-            // TODO also test if transaction it at index 0 in block
-            if tx.transaction.content.sender_addr == vec![0]
-            && tx.transaction.content.sender_pubkey == vec![0]
-            && tx.transaction.signature == vec![0] {
-                sender_addr = String::from("0");
-                sender_pubkey = String::from("0");
-                signature = String::from("0");
-            } else {
-                sender_addr = tx.transaction.content.sender_addr.to_base58();
-                sender_pubkey = tx.transaction.content.sender_pubkey.to_hex();
-                signature = tx.transaction.signature.to_hex();
-            }
-
-            NetTransaction {
-                id: id,
-                sender_addr: sender_addr,
-                sender_pubkey: sender_pubkey,
-                receiver_addr: receiver_addr,
-                amount: tx.transaction.content.amount,
-                timestamp: tx.transaction.content.timestamp,
-                signature: signature
-            }
-        }).collect();
+        let net_txs: Vec<NetTransaction> = block.transactions.iter()
+            .map(|tx| NetTransaction::from_transaction(tx))
+            .collect();
+
+        let net_utxo_txs: Vec<String> = block.utxo_transactions.iter()
+            .map(|tx| tx.to_raw())
+            .collect();
 
         NetBlock {
+            version: block.header.version as i32,
             id: block.header.id,
             timestamp: block.header.timestamp,
             previous_hash: previous_hash,
             merkle_root: merkle_root,
+            difficulty: block.header.difficulty as i32,
             hash: hash,
-            nonce: block.nonce,
-            transactions: net_txs
+            nonce: block.header.nonce as i64,
+            transactions: net_txs,
+            utxo_transactions: net_utxo_txs
         }
     }
 }
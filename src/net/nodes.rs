@@ -2,7 +2,7 @@ use std::thread;
 use reqwest;
 use rusqlite::Connection;
 
-use super::NetTransaction;
+use super::{NetTransaction, NetUtxoTransaction};
 use errors::CoreError;
 
 #[derive(Deserialize, Debug)]
@@ -95,3 +95,27 @@ pub fn send_transaction(tx: NetTransaction) -> Result<(), CoreError> {
 
     Ok(())
 }
+
+pub fn send_utxo_transaction(tx: NetUtxoTransaction) -> Result<(), CoreError> {
+    let nodes = get_nodes_from_db()?;
+
+    // spawn a thread to do not block the request
+    thread::spawn(move || {
+        // send transaction to known nodes
+        for n in nodes {
+            let url = format!("http://{}:{}/utxo/transaction", n.address, n.port);
+            let client = reqwest::Client::new();
+
+            match client.post(&url).json(&tx).send() {
+                Ok(r) => {
+                    println!("ok");
+                },
+                Err(e) => {
+                    println!("{}", e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -1,17 +1,53 @@
+use std::net::IpAddr;
 use rouille::{Server, Request, Response};
 
 use super::handlers;
 use errors::ServerError;
+use blockchain;
+use mempool;
+use params;
+
+// address the node listens on
+const HOST: &'static str = "10.0.0.1";
+
+// check if a request comes from the node's own machine, the only one allowed to use the `/local` routes
+// (they control the wallets and the mining of the node)
+fn is_local(req: &Request) -> bool {
+    let ip: IpAddr = req.remote_addr().ip();
+
+    ip.is_loopback() || HOST.parse::<IpAddr>().map_or(false, |host| ip == host)
+}
 
 // route incoming requests to matching handler
 fn route(req: &Request) -> Result<Response, ServerError> {
+    if req.url().starts_with("/local/") && !is_local(req) {
+        return Err(ServerError::Forbidden);
+    }
+
     router!(req,
         (GET) (/) => { handlers::get_index(req) },
         (POST) (/transaction) => { handlers::post_transaction(req) },
         (POST) (/block) => { handlers::post_block(req) },
+        (GET) (/tx/{id}) => { handlers::get_tx(req, id) },
+        (GET) (/tx/{id}/proof) => { handlers::get_tx_proof(req, id) },
+        (GET) (/address/{address}/balance) => { handlers::get_address_balance(req, address) },
+        (GET) (/address/{address}/nonce) => { handlers::get_address_nonce(req, address) },
+        (GET) (/address/{address}/utxos) => { handlers::get_address_utxos(req, address) },
+        (POST) (/utxo/transaction) => { handlers::post_utxo_transaction(req) },
+        (GET) (/mining/template) => { handlers::get_mining_template(req) },
+        (POST) (/mining/submit) => { handlers::post_mining_submit(req) },
         (GET) (/local/wallet/new) => { handlers::local::get_new_wallet(req) },
         (GET) (/local/wallet/{address}) => { handlers::local::get_wallet(req, address) },
         (POST) (/local/transaction) => { handlers::local::post_transaction(req) },
+        (POST) (/local/utxo/transaction) => { handlers::local::post_utxo_transaction(req) },
+        (POST) (/local/multisig/address) => { handlers::local::post_multisig_address(req) },
+        (POST) (/local/multisig/transaction) => { handlers::local::post_multisig_transaction(req) },
+        (POST) (/local/multisig/sign) => { handlers::local::post_multisig_sign(req) },
+        (GET) (/local/mining) => { handlers::local::get_mining(req) },
+        (POST) (/local/mining/start) => { handlers::local::post_mining_start(req) },
+        (POST) (/local/mining/stop) => { handlers::local::post_mining_stop(req) },
+        (POST) (/local/mining/address) => { handlers::local::post_mining_address(req) },
+        (POST) (/local/mining/threads) => { handlers::local::post_mining_threads(req) },
         _ => Err(ServerError::NotFound)
     )
 }
@@ -27,12 +63,36 @@ fn handle(req: &Request) -> Response {
                 ServerError::NotFound => {
                     Response::empty_404()
                 },
+                ServerError::Forbidden => {
+                    Response::text("forbidden").with_status_code(403)
+                },
                 ServerError::InvalidTransaction => {
                     Response::empty_400()
                 },
-                ServerError::InvalidBlock => {
+                ServerError::InvalidParameter => {
                     Response::empty_400()
                 },
+                ServerError::DuplicateTransaction => {
+                    Response::text("duplicate transaction").with_status_code(409)
+                },
+                ServerError::InsufficientFunds => {
+                    Response::text("insufficient funds").with_status_code(400)
+                },
+                ServerError::InvalidNonce => {
+                    Response::text("invalid nonce").with_status_code(400)
+                },
+                ServerError::InsufficientFee => {
+                    Response::text("fee below the minimum relay fee").with_status_code(400)
+                },
+                ServerError::MempoolFull => {
+                    Response::text("mempool full").with_status_code(503)
+                },
+                ServerError::MissingInput => {
+                    Response::text("missing or spent input").with_status_code(400)
+                },
+                ServerError::InvalidBlock(reason) => {
+                    Response::text(format!("{:?}", reason)).with_status_code(400)
+                },
                 _ => {
                     println!("error: {:?}", e);
                     Response::text("error")
@@ -44,9 +104,19 @@ fn handle(req: &Request) -> Response {
 
 // start the http server
 pub fn start() {
-    println!("STARTING NODE...");
+    println!("STARTING NODE ON {:?} NETWORK...", params::get().network);
+
+    if let Err(e) = blockchain::init() {
+        panic!("Can't initialize the database: {:?}", e);
+    }
+
+    if let Err(e) = mempool::restore() {
+        println!("MEMPOOL NOT RESTORED {:?}", e);
+    }
+
+    let address = format!("{}:{}", HOST, params::get().port);
 
-    let server = Server::new("10.0.0.1:8000", |req| {
+    let server = Server::new(address, |req| {
         handle(&req)
     });
 
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use net::NetBlock;
+
+// maximum number of blocks kept while waiting for their parent
+const MAX_ORPHANS: usize = 100;
+
+lazy_static! {
+    // blocks whose parent is unknown, indexed by the hash of that parent
+    static ref ORPHANS: Mutex<HashMap<Vec<u8>, Vec<NetBlock>>> = Mutex::new(HashMap::new());
+}
+
+// keep a block until its parent arrives
+pub fn add(parent_hash: Vec<u8>, block: NetBlock) {
+    let mut orphans = ORPHANS.lock().unwrap();
+
+    // make room by dropping the blocks waiting for some parent
+    let count: usize = orphans.values().map(|blocks| blocks.len()).sum();
+    if count >= MAX_ORPHANS {
+        let evicted: Option<Vec<u8>> = orphans.keys().next().cloned();
+        if let Some(key) = evicted {
+            orphans.remove(&key);
+        }
+    }
+
+    let children = orphans.entry(parent_hash).or_insert(Vec::new());
+    if !children.iter().any(|b| b.hash == block.hash) {
+        children.push(block);
+    }
+}
+
+// remove and return the blocks waiting for the given parent
+pub fn take_children(parent_hash: &Vec<u8>) -> Vec<NetBlock> {
+    ORPHANS.lock().unwrap().remove(parent_hash).unwrap_or(Vec::new())
+}
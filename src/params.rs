@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use amount::COIN;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Network {
+    Main,
+    Test,
+    Regtest
+}
+
+// fields of the hardcoded genesis header, the other ones are null
+// (version 1, id 0, null previous_hash, null merkle_root as the genesis has no transaction)
+pub struct GenesisParams {
+    pub timestamp: i64,
+    pub nonce: u64,
+    pub hash: &'static str,
+}
+
+pub struct ChainParams {
+    pub network: Network,
+    // identifies the network a database belongs to
+    pub network_id: &'static str,
+    pub port: u16,
+    // difficulty of the genesis, inherited by the first blocks
+    pub initial_difficulty: u32,
+    // subsidy schedule, amounts in base units
+    pub initial_subsidy: u64,
+    pub halving_interval: i32,
+    pub max_supply: u64,
+    // height of the first block carrying UTXO transactions (block version 2)
+    pub utxo_activation_height: i32,
+    pub genesis: GenesisParams,
+}
+
+static MAIN: ChainParams = ChainParams {
+    network: Network::Main,
+    network_id: "simplechain-main",
+    port: 8000,
+    initial_difficulty: 20,
+    initial_subsidy: 50 * COIN,
+    halving_interval: 100000,
+    max_supply: 9500000 * COIN,
+    utxo_activation_height: 20000,
+    genesis: GenesisParams {
+        timestamp: 1506816000000,
+        nonce: 986553,
+        hash: "0000098289826ccabf2b4854ce42bc22e474aab1e0f44fa2abecaa4a726ecfa4"
+    }
+};
+
+static TEST: ChainParams = ChainParams {
+    network: Network::Test,
+    network_id: "simplechain-test",
+    port: 18000,
+    initial_difficulty: 12,
+    initial_subsidy: 50 * COIN,
+    halving_interval: 100000,
+    max_supply: 9500000 * COIN,
+    utxo_activation_height: 1000,
+    genesis: GenesisParams {
+        timestamp: 1506816000001,
+        nonce: 500,
+        hash: "0005a7e10ab20c626d492a9aae2d12e1c41afbff8a1a214ecc6aac9a476b17e1"
+    }
+};
+
+// local network for testing, blocks are instantly mined and the subsidy halves quickly
+static REGTEST: ChainParams = ChainParams {
+    network: Network::Regtest,
+    network_id: "simplechain-regtest",
+    port: 28000,
+    initial_difficulty: 1,
+    initial_subsidy: 50 * COIN,
+    halving_interval: 150,
+    max_supply: 10000 * COIN,
+    utxo_activation_height: 10,
+    genesis: GenesisParams {
+        timestamp: 1506816000002,
+        nonce: 1,
+        hash: "6669d349b680d4122335f4803037c76c92a11690dcb6ec27098c8bc3715328aa"
+    }
+};
+
+// index of the selected network's params
+static NETWORK: AtomicUsize = AtomicUsize::new(0);
+
+// select the network the node runs on, must be called before anything else
+pub fn select(network: Network) {
+    let index = match network {
+        Network::Main => 0,
+        Network::Test => 1,
+        Network::Regtest => 2
+    };
+
+    NETWORK.store(index, Ordering::SeqCst);
+}
+
+// get the params of the selected network
+pub fn get() -> &'static ChainParams {
+    match NETWORK.load(Ordering::SeqCst) {
+        1 => &TEST,
+        2 => &REGTEST,
+        _ => &MAIN
+    }
+}
@@ -0,0 +1,265 @@
+use std::convert::TryFrom;
+use secp256k1;
+use secp256k1::key::{SecretKey, PublicKey};
+use k256::schnorr;
+use k256::schnorr::signature::hazmat::{PrehashSigner, PrehashVerifier};
+
+use errors::CoreError;
+use wallet;
+
+// largest signature of the supported schemes (DER encoded ECDSA)
+pub const MAX_SIGNATURE_SIZE: usize = 72;
+// size of an experimental schnorr signature, which the fork can't read at another size
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+// schemes a transaction can be signed with
+// the tag is part of the signed content, so that a signature can't be read under another scheme
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SignatureScheme {
+    Schnorr, // experimental schnorr signatures of the secp256k1 fork, the only scheme of version 1 UTXO transactions
+    Bip340, // standard schnorr signatures (BIP-340) of the x-only public key
+    Ecdsa, // DER encoded ECDSA signatures with a low S
+}
+
+impl SignatureScheme {
+    pub fn from_tag(tag: u8) -> Option<SignatureScheme> {
+        match tag {
+            0 => Some(SignatureScheme::Schnorr),
+            1 => Some(SignatureScheme::Bip340),
+            2 => Some(SignatureScheme::Ecdsa),
+            _ => None
+        }
+    }
+
+    pub fn to_tag(&self) -> u8 {
+        match *self {
+            SignatureScheme::Schnorr => 0,
+            SignatureScheme::Bip340 => 1,
+            SignatureScheme::Ecdsa => 2
+        }
+    }
+}
+
+// sign a message (hash of the signed content) with the given scheme
+pub fn sign(
+    secp: &secp256k1::Secp256k1,
+    scheme: SignatureScheme,
+    message: &secp256k1::Message,
+    private_key: &SecretKey
+) -> Result<Vec<u8>, CoreError> {
+    match scheme {
+        SignatureScheme::Schnorr => Ok(secp.sign_schnorr(message, private_key)?.serialize()),
+        SignatureScheme::Bip340 => {
+            // the key is negated if needed, so that its public key has an even y as BIP-340 requires
+            let signing_key = schnorr::SigningKey::from_bytes(&private_key[..]).map_err(|_| CoreError::CryptoError)?;
+            let signature = signing_key.sign_prehash(&message[..]).map_err(|_| CoreError::CryptoError)?;
+            Ok(signature.to_bytes().to_vec())
+        },
+        SignatureScheme::Ecdsa => {
+            let mut signature = secp.sign(message, private_key)?;
+            signature.normalize_s(secp);
+            Ok(signature.serialize_der(secp))
+        }
+    }
+}
+
+// check a signature of a message by the given compressed public key
+pub fn verify(
+    secp: &secp256k1::Secp256k1,
+    scheme: SignatureScheme,
+    message: &secp256k1::Message,
+    signature: &Vec<u8>,
+    public_key: &Vec<u8>
+) -> bool {
+    match scheme {
+        SignatureScheme::Schnorr => {
+            if signature.len() != SCHNORR_SIGNATURE_SIZE {
+                return false;
+            }
+
+            let signature = secp256k1::schnorr::Signature::deserialize(signature);
+            let public_key = match PublicKey::from_slice(secp, public_key) {
+                Ok(public_key) => public_key,
+                Err(_) => return false
+            };
+
+            secp.verify_schnorr(message, &signature, &public_key).is_ok()
+        },
+        SignatureScheme::Bip340 => {
+            // the x-only public key is the compressed key without its parity byte
+            if public_key.len() != wallet::PUBLIC_KEY_SIZE {
+                return false;
+            }
+
+            let verifying_key = match schnorr::VerifyingKey::from_bytes(&public_key[1..]) {
+                Ok(verifying_key) => verifying_key,
+                Err(_) => return false
+            };
+            let signature = match schnorr::Signature::try_from(&signature[..]) {
+                Ok(signature) => signature,
+                Err(_) => return false
+            };
+
+            verifying_key.verify_prehash(&message[..], &signature).is_ok()
+        },
+        SignatureScheme::Ecdsa => {
+            let parsed = match secp256k1::Signature::from_der(secp, signature) {
+                Ok(parsed) => parsed,
+                Err(_) => return false
+            };
+
+            // only the strict DER encoding with a low S is accepted
+            // any other encoding of the same signature would give the transaction another id
+            let mut normalized = parsed.clone();
+            normalized.normalize_s(secp);
+            if normalized.serialize_der(secp) != *signature {
+                return false;
+            }
+
+            let public_key = match PublicKey::from_slice(secp, public_key) {
+                Ok(public_key) => public_key,
+                Err(_) => return false
+            };
+
+            secp.verify(message, &parsed, &public_key).is_ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMES: [SignatureScheme; 3] = [SignatureScheme::Schnorr, SignatureScheme::Bip340, SignatureScheme::Ecdsa];
+
+    // order of the secp256k1 group
+    const ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+        0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41
+    ];
+
+    fn get_keys(secp: &secp256k1::Secp256k1, seed: u8) -> (SecretKey, Vec<u8>) {
+        let private_key = SecretKey::from_slice(secp, &[seed; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(secp, &private_key).unwrap();
+
+        (private_key, public_key.serialize_vec(secp, true).as_slice().to_vec())
+    }
+
+    fn get_message(seed: u8) -> secp256k1::Message {
+        secp256k1::Message::from_slice(&[seed; 32]).unwrap()
+    }
+
+    // split a DER signature into its r and s integers
+    fn split_der(der: &Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let r_size = der[3] as usize;
+        let s_size = der[5 + r_size] as usize;
+
+        (der[4..4 + r_size].to_vec(), der[6 + r_size..6 + r_size + s_size].to_vec())
+    }
+
+    fn join_der(r: &[u8], s: &[u8]) -> Vec<u8> {
+        let mut der: Vec<u8> = vec![0x30, (4 + r.len() + s.len()) as u8, 0x02, r.len() as u8];
+        der.extend_from_slice(r);
+        der.extend_from_slice(&[0x02, s.len() as u8]);
+        der.extend_from_slice(s);
+        der
+    }
+
+    // the high S of a signature with a low S: the order minus S
+    fn get_high_s(s: &[u8]) -> Vec<u8> {
+        let mut padded: Vec<u8> = vec![0; 32 - s.len()];
+        padded.extend_from_slice(s);
+
+        let mut high: Vec<u8> = vec![0; 32];
+        let mut borrow: i16 = 0;
+        for i in (0..32).rev() {
+            let mut difference: i16 = ORDER[i] as i16 - padded[i] as i16 - borrow;
+            borrow = if difference < 0 { 1 } else { 0 };
+            if difference < 0 {
+                difference += 256;
+            }
+            high[i] = difference as u8;
+        }
+
+        // a leading null byte keeps the integer positive
+        if high[0] >= 0x80 {
+            high.insert(0, 0);
+        }
+        high
+    }
+
+    #[test]
+    fn signatures_verify_under_their_scheme() {
+        let secp = secp256k1::Secp256k1::new();
+        let (private_key, public_key) = get_keys(&secp, 1);
+        let (_, other_public_key) = get_keys(&secp, 2);
+
+        for scheme in SCHEMES.iter() {
+            let signature = sign(&secp, *scheme, &get_message(7), &private_key).unwrap();
+
+            assert!(signature.len() <= MAX_SIGNATURE_SIZE, "{:?}", scheme);
+            assert!(verify(&secp, *scheme, &get_message(7), &signature, &public_key), "{:?}", scheme);
+            assert!(!verify(&secp, *scheme, &get_message(8), &signature, &public_key), "{:?}", scheme);
+            assert!(!verify(&secp, *scheme, &get_message(7), &signature, &other_public_key), "{:?}", scheme);
+            assert_eq!(SignatureScheme::from_tag(scheme.to_tag()), Some(*scheme));
+
+            // a signature can't be read under another scheme
+            for other in SCHEMES.iter().filter(|other| *other != scheme) {
+                assert!(!verify(&secp, *other, &get_message(7), &signature, &public_key), "{:?} as {:?}", scheme, other);
+            }
+        }
+
+        assert_eq!(SignatureScheme::from_tag(3), None);
+    }
+
+    #[test]
+    fn malformed_keys_and_signatures_are_rejected() {
+        let secp = secp256k1::Secp256k1::new();
+        let (private_key, public_key) = get_keys(&secp, 1);
+
+        for scheme in SCHEMES.iter() {
+            let signature = sign(&secp, *scheme, &get_message(7), &private_key).unwrap();
+
+            assert!(!verify(&secp, *scheme, &get_message(7), &signature, &vec![2; 10]), "{:?}", scheme);
+            assert!(!verify(&secp, *scheme, &get_message(7), &Vec::new(), &public_key), "{:?}", scheme);
+        }
+    }
+
+    #[test]
+    fn ecdsa_rejects_a_high_s() {
+        let secp = secp256k1::Secp256k1::new();
+        let (private_key, public_key) = get_keys(&secp, 1);
+
+        let signature = sign(&secp, SignatureScheme::Ecdsa, &get_message(7), &private_key).unwrap();
+        let (r, s) = split_der(&signature);
+        assert_eq!(join_der(&r, &s), signature);
+
+        // the same signature with the other S, which normalizes back to the signed one
+        let high_s = join_der(&r, &get_high_s(&s));
+        if let Ok(mut parsed) = secp256k1::Signature::from_der(&secp, &high_s) {
+            parsed.normalize_s(&secp);
+            assert_eq!(parsed.serialize_der(&secp), signature);
+        }
+
+        assert!(!verify(&secp, SignatureScheme::Ecdsa, &get_message(7), &high_s, &public_key));
+    }
+
+    #[test]
+    fn ecdsa_rejects_a_non_strict_der() {
+        let secp = secp256k1::Secp256k1::new();
+        let (private_key, public_key) = get_keys(&secp, 1);
+
+        let signature = sign(&secp, SignatureScheme::Ecdsa, &get_message(7), &private_key).unwrap();
+        let (r, s) = split_der(&signature);
+
+        // an integer padded with a useless null byte
+        let mut padded_r: Vec<u8> = vec![0];
+        padded_r.extend_from_slice(&r);
+        assert!(!verify(&secp, SignatureScheme::Ecdsa, &get_message(7), &join_der(&padded_r, &s), &public_key));
+
+        // a trailing byte after the sequence
+        let mut trailing: Vec<u8> = signature.clone();
+        trailing.push(0);
+        assert!(!verify(&secp, SignatureScheme::Ecdsa, &get_message(7), &trailing, &public_key));
+    }
+}
@@ -0,0 +1,141 @@
+use postgres::GenericConnection;
+use base58::{FromBase58, ToBase58};
+
+use net::NetTransaction;
+use amount::Amount;
+use errors::CoreError;
+use blockchain;
+use mempool;
+
+// balance and nonce (number of sent transactions) of every address, as of the main chain's tip
+pub const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS balances (
+        address TEXT PRIMARY KEY,
+        amount BIGINT NOT NULL
+    );
+
+    ALTER TABLE balances ADD COLUMN IF NOT EXISTS nonce BIGINT NOT NULL DEFAULT 0;
+";
+
+// apply the transactions of a block being connected to the balances
+// or undo them if the block is being disconnected
+pub fn apply_transactions<C: GenericConnection>(
+    conn: &C,
+    transactions: &Vec<NetTransaction>,
+    revert: bool
+) -> Result<(), CoreError> {
+    let sign: i64 = if revert { -1 } else { 1 };
+
+    for tx in transactions {
+        // coinbase transactions have no sender to debit, the fee goes to the coinbase's receiver
+        if tx.sender_addr != "0" {
+            let debit: Amount = tx.get_debit().ok_or(CoreError::AmountOverflow)?;
+            update_account(conn, &tx.sender_addr, -sign * debit.to_i64(), sign)?;
+        }
+
+        update_account(conn, &tx.receiver_addr, sign * tx.amount.to_i64(), 0)?;
+
+        for payment in &tx.payments {
+            update_account(conn, &payment.address, sign * payment.amount.to_i64(), 0)?;
+        }
+    }
+
+    Ok(())
+}
+
+// add the given amounts to the balance and the nonce of an address
+// the balance of a valid chain never goes negative nor over the largest amount
+fn update_account<C: GenericConnection>(
+    conn: &C,
+    address: &String,
+    amount: i64,
+    nonce: i64
+) -> Result<(), CoreError> {
+    conn.execute(
+        "INSERT INTO balances(address, amount, nonce) VALUES($1, $2, $3)
+        ON CONFLICT (address) DO UPDATE SET
+            amount = balances.amount + EXCLUDED.amount,
+            nonce = balances.nonce + EXCLUDED.nonce",
+        &[address, &amount, &nonce]
+    )?;
+
+    Ok(())
+}
+
+// get the balance of an address as of the main chain's tip
+pub fn get_confirmed_balance(address: &String) -> Result<Amount, CoreError> {
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    get_balance(&*conn, address)
+}
+
+pub fn get_balance<C: GenericConnection>(conn: &C, address: &String) -> Result<Amount, CoreError> {
+    let rows = conn.query("SELECT amount FROM balances WHERE address = $1", &[address])?;
+
+    if !rows.is_empty() {
+        match rows.get(0).get_opt(0) {
+            Some(Ok(balance)) => Ok(balance),
+            _ => Err(CoreError::DatabaseError)
+        }
+    } else {
+        Ok(Amount::zero())
+    }
+}
+
+// get the nonce the next transaction of an address must use, as of the main chain's tip
+pub fn get_confirmed_nonce(address: &String) -> Result<u64, CoreError> {
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    get_nonce(&*conn, address)
+}
+
+pub fn get_nonce<C: GenericConnection>(conn: &C, address: &String) -> Result<u64, CoreError> {
+    let rows = conn.query("SELECT nonce FROM balances WHERE address = $1", &[address])?;
+
+    if !rows.is_empty() {
+        let nonce: i64 = rows.get(0).get(0);
+        Ok(nonce as u64)
+    } else {
+        Ok(0)
+    }
+}
+
+// get the nonce the next transaction of an address must use, after its pending transactions
+pub fn get_next_nonce(address: &Vec<u8>) -> Result<u64, CoreError> {
+    let mut nonce: u64 = get_confirmed_nonce(&address.to_base58())?;
+
+    for tx in mempool::get_transactions() {
+        if tx.transaction.content.sender_addr == *address {
+            nonce += 1;
+        }
+    }
+
+    Ok(nonce)
+}
+
+// get the balance change of an address caused by the pending transactions, in base units (can be negative)
+pub fn get_pending_balance(address: &String) -> Result<i64, CoreError> {
+    let address_bytes: Vec<u8> = address.from_base58()?;
+    let mut pending: i64 = 0;
+
+    for tx in mempool::get_transactions() {
+        let content = &tx.transaction.content;
+
+        // the mempool only accepts transactions their senders can afford, which can't overflow
+        if content.sender_addr == address_bytes {
+            pending -= tx.get_debit().ok_or(CoreError::AmountOverflow)?.to_i64();
+        }
+        if content.receiver_addr == address_bytes {
+            pending += content.amount.to_i64();
+        }
+        for payment in &content.payments {
+            if payment.address == address_bytes {
+                pending += payment.amount.to_i64();
+            }
+        }
+    }
+
+    Ok(pending)
+}
@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use hex::ToHex;
+
+use blocks::Block;
+
+// maximum number of templates kept for external miners
+const MAX_TEMPLATES: usize = 32;
+
+lazy_static! {
+    // templates handed out to external miners, oldest first
+    static ref TEMPLATES: Mutex<VecDeque<(String, Block)>> = Mutex::new(VecDeque::new());
+}
+
+// keep a template for miners to submit nonces for it and return its id
+// the id is the hash of the header with a null nonce
+pub fn add(template: Block) -> String {
+    let id: String = template.header.get_hash().to_hex();
+    let mut templates = TEMPLATES.lock().unwrap();
+
+    if templates.len() >= MAX_TEMPLATES {
+        templates.pop_front();
+    }
+    templates.push_back((id.clone(), template));
+
+    id
+}
+
+// get a copy of the template with the given id
+pub fn get(id: &String) -> Option<Block> {
+    let templates = TEMPLATES.lock().unwrap();
+
+    templates.iter()
+        .find(|&&(ref template_id, _)| template_id == id)
+        .map(|&(_, ref template)| template.clone())
+}
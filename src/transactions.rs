@@ -1,47 +1,84 @@
+use std::iter;
 use bincode::{serialize, deserialize, Infinite};
 use sha2::{Sha256, Digest};
-use rusqlite::Connection;
 use base58::{FromBase58, ToBase58};
 use hex::{FromHex, ToHex};
 use secp256k1;
-use secp256k1::key::{SecretKey, PublicKey};
-use jfs;
+use secp256k1::key::SecretKey;
 
-use net::{NetTransaction, NetKeyPair};
+use net::{NetTransaction, NetPayment};
+use amount::{Amount, COIN};
 use errors::CoreError;
+use multisig;
+use multisig::MultisigPolicy;
+use monetary;
+use difficulty;
+use signature::{self, SignatureScheme};
 use utils;
+use wallet;
+
+// minimum fee of a relayed transaction in base units, per started kilobyte (0.00001 coin)
+pub const MIN_RELAY_FEE_RATE: u64 = COIN / 100000;
+// maximum number of additional receivers of a batch payment
+pub const MAX_PAYMENTS: usize = 1000;
+// lock times below are block heights, the others are timestamps in milliseconds
+pub const LOCK_TIME_THRESHOLD: u64 = 500000000;
+// furthest a relayed transaction can be locked ahead of the current time (ms), or the equivalent number of blocks
+pub const MAX_LOCK_DELAY: i64 = 30 * 24 * 3600 * 1000;
+// maximum size of the data carried by a transaction
+pub const MAX_DATA_SIZE: usize = 256;
 
 // FIXME too many public fields
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct TransactionContent {
     pub sender_addr: Vec<u8>,
     pub sender_pubkey: Vec<u8>,
     pub receiver_addr: Vec<u8>,
-    pub amount: i32,
-    pub timestamp: i64
+    pub amount: Amount,
+    pub payments: Vec<Payment>, // other receivers of a batch payment, paid under the same signature
+    pub fee: Amount, // paid by the sender on top of the amount, collected by the miner
+    pub nonce: u64, // number of transactions previously sent by the sender
+    pub lock_time: u64, // height or time before which the transaction can't be included, 0 if none
+    pub data: Vec<u8>, // free-form payload such as an invoice reference, empty if none
+    pub timestamp: i64,
+    pub signature_scheme: u8 // tag of the scheme of the signature, see `SignatureScheme`
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Payment {
+    pub address: Vec<u8>,
+    pub amount: Amount,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct TransactionSigned {
     pub content: TransactionContent,
     pub signature: Vec<u8>
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Transaction {
     pub id: Vec<u8>,
     pub transaction: TransactionSigned // bad field name...
 }
 
 impl TransactionContent {
-    // sign a transaction using schnorr signature
+    // get the amount paid to every receiver, none if it overflows
+    pub fn get_total_amount(&self) -> Option<Amount> {
+        let amounts = self.payments.iter().map(|payment| payment.amount);
+        Amount::checked_sum(iter::once(self.amount).chain(amounts))
+    }
+
+    // sign a transaction using the signature scheme it is tagged with
     pub fn get_signature(
         &self,
         private_key: SecretKey
     ) -> Result<Vec<u8>, CoreError> {
         println!("SIGN TRANSACTION");
 
+        let scheme = SignatureScheme::from_tag(self.signature_scheme).ok_or(CoreError::CryptoError)?;
+
         let secp = secp256k1::Secp256k1::new();
         // serialize the tx content
         let tx_content_encoded: Vec<u8> = serialize(&self, Infinite)?;
@@ -55,7 +92,7 @@ impl TransactionContent {
         let input = secp256k1::Message::from_slice(tx_content_hashed.as_slice())?;
 
         // return the signature created with the input message and private key
-        Ok(secp.sign_schnorr(&input, &private_key)?.serialize())
+        signature::sign(&secp, scheme, &input, &private_key)
     }
 }
 
@@ -80,11 +117,99 @@ impl Transaction {
         Ok(tx)
     }
 
+    // check an id received with the transaction against the one derived from it
+    pub fn has_id(&self, id: &String) -> bool {
+        id.to_lowercase() == self.id.to_hex()
+    }
+
+    // a coinbase transaction has no sender and no signature
+    pub fn is_coinbase(&self) -> bool {
+        self.transaction.content.sender_addr == vec![0]
+        && self.transaction.content.sender_pubkey == vec![0]
+        && self.transaction.signature == vec![0]
+    }
+
+    // a transaction sent from a multisig address carries the serialized policy instead of a public key
+    pub fn is_multisig(&self) -> bool {
+        self.transaction.content.sender_pubkey.len() != wallet::PUBLIC_KEY_SIZE
+    }
+
+    // check if the transaction can be included in the block at the given height and time
+    // blocks are checked against the median time past of their parent, which their miner can't move forward
+    pub fn is_final(&self, height: i32, timestamp: i64) -> bool {
+        let lock_time = self.transaction.content.lock_time;
+
+        if lock_time == 0 {
+            true
+        } else if lock_time < LOCK_TIME_THRESHOLD {
+            height as u64 >= lock_time
+        } else {
+            timestamp as u64 >= lock_time
+        }
+    }
+
+    // check if the lock time isn't further than `MAX_LOCK_DELAY` ahead of the given height and time
+    // a transaction locked further would sit in the mempool, holding the sender's following transactions
+    pub fn is_lock_time_within_reach(&self, height: i32, timestamp: i64) -> bool {
+        let lock_time = self.transaction.content.lock_time;
+
+        if lock_time < LOCK_TIME_THRESHOLD {
+            lock_time <= height as u64 + (MAX_LOCK_DELAY / difficulty::TARGET_BLOCK_TIME) as u64
+        } else {
+            lock_time <= timestamp.saturating_add(MAX_LOCK_DELAY) as u64
+        }
+    }
+
+    // get the fee paid to the miner including the transaction
+    pub fn get_fee(&self) -> Amount {
+        self.transaction.content.fee
+    }
+
+    // get the amount debited from the sender: every payment and the fee, none if it overflows
+    pub fn get_debit(&self) -> Option<Amount> {
+        let content = &self.transaction.content;
+
+        content.get_total_amount().and_then(|total| total.checked_add(content.fee))
+    }
+
+    // get the size of the serialized transaction
+    pub fn get_size(&self) -> usize {
+        // can't fail as the size is unbounded
+        serialize(&self, Infinite).unwrap().len()
+    }
+
+    // get the fee paid per kilobyte, used to prioritize transactions in blocks
+    pub fn get_fee_rate(&self) -> u64 {
+        get_fee_rate(self.get_fee(), self.get_size())
+    }
+
     // verify a transaction using the signature and the public key
     pub fn is_valid(&self) -> Result<bool, CoreError> {
+        self.is_valid_with(&secp256k1::Secp256k1::new())
+    }
+
+    // same as `is_valid`, with a context shared by the caller instead of a new one
+    pub fn is_valid_with(&self, secp: &secp256k1::Secp256k1) -> Result<bool, CoreError> {
         println!("VERIFY TRANSACTION");
 
-        let secp = secp256k1::Secp256k1::new();
+        let content = &self.transaction.content;
+
+        // every receiver of a batch payment gets something, and the total debited must fit in an amount
+        if content.payments.len() > MAX_PAYMENTS
+            || content.payments.iter().any(|payment| payment.amount.is_zero())
+            || self.get_debit().is_none() {
+            return Ok(false);
+        }
+
+        if content.data.len() > MAX_DATA_SIZE {
+            return Ok(false);
+        }
+
+        let scheme = match SignatureScheme::from_tag(content.signature_scheme) {
+            Some(scheme) => scheme,
+            None => return Ok(false)
+        };
+
         // serialize the tx content
         let tx_encoded: Vec<u8> = serialize(&self.transaction.content, Infinite)?;
 
@@ -96,43 +221,33 @@ impl Transaction {
         // create the input message using the hashed tx content
         let input = secp256k1::Message::from_slice(tx_hashed.as_slice())?;
 
-        // retrieve sig and pbkey from the tx
-        let signature = secp256k1::schnorr::Signature::deserialize(&self.transaction.signature);
-        let public_key = PublicKey::from_slice(
-            &secp, &self.transaction.content.sender_pubkey
-        )?;
+        if self.is_multisig() {
+            let policy = match MultisigPolicy::from_bytes(&content.sender_pubkey) {
+                Ok(policy) => policy,
+                Err(_) => return Ok(false)
+            };
 
-        // verify the input message using the signature and pbkey
-        Ok(
-            match secp.verify_schnorr(&input, &signature, &public_key) {
-                Ok(()) => true,
-                _ => false
+            // the policy must be the one the sender address was derived from
+            if !policy.is_valid() || policy.get_address() != content.sender_addr {
+                return Ok(false);
             }
-        )
-    }
 
-    // store a transaction on database (cache) for further block creation
-    // TODO rewrite this with redis
-    pub fn store_db(&self) -> Result<(), CoreError> {
-        println!("STORE TRANSACTION [DB]");
-        // TODO rewrite this with connection pools
-        // TODO get the db address string from config.json
-        let conn = Connection::open("db/storage.db")?;
-
-        let id = &self.id.to_hex();
-        let sender_addr = &self.transaction.content.sender_addr.to_base58();
-        let sender_pubkey = &self.transaction.content.sender_pubkey.to_hex();
-        let receiver_addr = &self.transaction.content.receiver_addr.to_base58();
-        let amount = &self.transaction.content.amount;
-        let timestamp = &self.transaction.content.timestamp;
-        let signature = &self.transaction.signature.to_hex();
-
-        conn.execute("INSERT INTO transactions(
-            id, sender_addr, sender_pubkey, receiver_addr, amount, timestamp, signature
-        ) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            &[&*id, &*sender_addr, &*sender_pubkey, &*receiver_addr, &*amount, &*timestamp, &*signature])?;
-
-        Ok(())
+            let signatures = match multisig::decode_signatures(&self.transaction.signature) {
+                Ok(signatures) => signatures,
+                Err(_) => return Ok(false)
+            };
+
+            // enough keys of the policy must have signed the content
+            return policy.verify(secp, scheme, &input, &signatures);
+        }
+
+        // the key must be the one the sender address was derived from
+        if wallet::get_address(&content.sender_pubkey) != content.sender_addr {
+            return Ok(false);
+        }
+
+        // verify the input message using the signature and pbkey
+        Ok(signature::verify(secp, scheme, &input, &self.transaction.signature, &content.sender_pubkey))
     }
 }
 
@@ -142,112 +257,201 @@ pub fn new(
     sender_pubkey: Vec<u8>,
     sender_addr: Vec<u8>,
     receiver_addr: Vec<u8>,
-    amount: i32
+    amount: Amount,
+    payments: Vec<Payment>,
+    fee: Amount,
+    nonce: u64,
+    lock_time: u64,
+    data: Vec<u8>,
+    signature_scheme: SignatureScheme
 ) -> Result<NetTransaction, CoreError> {
     println!("CREATE TRANSACTION");
 
-    let timestamp: i64 = utils::get_current_timestamp();
-
-    let tx_content = TransactionContent {
-        sender_addr: sender_addr,
-        sender_pubkey: sender_pubkey,
-        receiver_addr: receiver_addr,
-        amount: amount,
-        timestamp: timestamp
-    };
+    let mut tx = new_unsigned(sender_pubkey, sender_addr, receiver_addr, amount, payments, fee, nonce, lock_time, data, signature_scheme)?;
 
     // sign the current tx content
-    let signature: Vec<u8> = tx_content.get_signature(sender_privkey)?;
-
-    // create a signed tx with the signature
-    let tx_signed = TransactionSigned {
-        content: tx_content,
-        signature: signature
-    };
+    tx.transaction.signature = tx.transaction.content.get_signature(sender_privkey)?;
 
     // get the tx id (hash) using the signed tx content
-    let id: Vec<u8> = tx_signed.get_id()?;
+    tx.id = tx.transaction.get_id()?;
+    let tx_signed = &tx.transaction;
 
     // TEST
     println!("-- TRANSACTION --");
-    println!("id: {}", id.to_hex());
+    println!("id: {}", tx.id.to_hex());
     println!("sender_addr: {}", tx_signed.content.sender_addr.to_base58());
     println!("sender_pubkey: {}", tx_signed.content.sender_pubkey.to_hex());
     println!("receiver_addr: {}", tx_signed.content.receiver_addr.to_base58());
     println!("amount: {}", tx_signed.content.amount);
+    for payment in &tx_signed.content.payments {
+        println!("payment: {} {}", payment.address.to_base58(), payment.amount);
+    }
+    println!("fee: {}", tx_signed.content.fee);
+    println!("nonce: {}", tx_signed.content.nonce);
+    println!("lock_time: {}", tx_signed.content.lock_time);
+    println!("data: {}", tx_signed.content.data.to_hex());
     println!("timestamp: {}", tx_signed.content.timestamp);
+    println!("signature_scheme: {}", tx_signed.content.signature_scheme);
     println!("signature: {}", tx_signed.signature.to_hex());
 
     // return the final network transaction
-    let id = id.to_hex();
-    let sender_addr = tx_signed.content.sender_addr.to_base58();
-    let sender_pubkey = tx_signed.content.sender_pubkey.to_hex();
-    let receiver_addr = tx_signed.content.receiver_addr.to_base58();
-    let amount = tx_signed.content.amount;
-    let timestamp = tx_signed.content.timestamp;
-    let signature = tx_signed.signature.to_hex();
-
-    Ok(NetTransaction {
-        id: id,
-        sender_addr: sender_addr,
-        sender_pubkey: sender_pubkey,
-        receiver_addr: receiver_addr,
-        amount: amount,
-        timestamp: timestamp,
-        signature: signature
+    Ok(NetTransaction::from_transaction(&tx))
+}
+
+// create a transaction without signature, to be signed by the keys of a multisig policy
+pub fn new_unsigned(
+    sender_pubkey: Vec<u8>,
+    sender_addr: Vec<u8>,
+    receiver_addr: Vec<u8>,
+    amount: Amount,
+    payments: Vec<Payment>,
+    fee: Amount,
+    nonce: u64,
+    lock_time: u64,
+    data: Vec<u8>,
+    signature_scheme: SignatureScheme
+) -> Result<Transaction, CoreError> {
+    let timestamp: i64 = utils::get_current_timestamp();
+
+    let tx_signed = TransactionSigned {
+        content: TransactionContent {
+            sender_addr: sender_addr,
+            sender_pubkey: sender_pubkey,
+            receiver_addr: receiver_addr,
+            amount: amount,
+            payments: payments,
+            fee: fee,
+            nonce: nonce,
+            lock_time: lock_time,
+            data: data,
+            timestamp: timestamp,
+            signature_scheme: signature_scheme.to_tag()
+        },
+        signature: Vec::new()
+    };
+
+    Ok(Transaction {
+        id: tx_signed.get_id()?,
+        transaction: tx_signed
     })
 }
 
 // return a Transaction struct filled with given field values
+// the id is always derived from the content and the signature, never taken from the sender
 pub fn from(
-    id: &String,
     sender_addr: &String,
     sender_pubkey: &String,
     receiver_addr: &String,
-    amount: i32,
+    amount: Amount,
+    payments: &Vec<NetPayment>,
+    fee: Amount,
+    nonce: u64,
+    lock_time: u64,
+    data: &String,
     timestamp: i64,
+    signature_scheme: u8,
     signature: &String,
 ) -> Result<Transaction, CoreError> {
-    let id: Vec<u8> = FromHex::from_hex(id)?;
     let sender_addr: Vec<u8> = sender_addr.from_base58()?;
     let sender_pubkey: Vec<u8> = FromHex::from_hex(sender_pubkey)?;
     let receiver_addr: Vec<u8> = receiver_addr.from_base58()?;
+    let data: Vec<u8> = FromHex::from_hex(data)?;
     let signature: Vec<u8> = FromHex::from_hex(signature)?;
 
+    let tx_signed = TransactionSigned {
+        content: TransactionContent {
+            sender_addr: sender_addr,
+            sender_pubkey: sender_pubkey,
+            receiver_addr: receiver_addr,
+            amount: amount,
+            payments: payments_from_net(payments)?,
+            fee: fee,
+            nonce: nonce,
+            lock_time: lock_time,
+            data: data,
+            timestamp: timestamp,
+            signature_scheme: signature_scheme
+        },
+        signature: signature,
+    };
+
     Ok(Transaction {
-        id: id,
-        transaction: TransactionSigned {
+        id: tx_signed.get_id()?,
+        transaction: tx_signed
+    })
+}
+
+// return a Transaction struct from a network transaction
+// the id of the network transaction is ignored, use `has_id` to check it against the derived one
+pub fn from_net(tx: &NetTransaction) -> Result<Transaction, CoreError> {
+    // an out of range tag would be truncated into another one
+    if tx.signature_scheme < 0 || tx.signature_scheme > u8::max_value() as i16 {
+        return Err(CoreError::SerializeError);
+    }
+
+    // negative values would be cast into huge ones
+    if tx.nonce < 0 || tx.lock_time < 0 {
+        return Err(CoreError::SerializeError);
+    }
+
+    // the sender fields and signature of a coinbase are "0", which isn't valid base58
+    if tx.sender_addr == "0" && tx.sender_pubkey == "0" && tx.signature == "0" {
+        let coinbase: Vec<u8> = vec![0];
+
+        let tx_signed = TransactionSigned {
             content: TransactionContent {
-                sender_addr: sender_addr,
-                sender_pubkey: sender_pubkey,
-                receiver_addr: receiver_addr,
-                amount: amount,
-                timestamp: timestamp
+                sender_addr: coinbase.clone(),
+                sender_pubkey: coinbase.clone(),
+                receiver_addr: tx.receiver_addr.from_base58()?,
+                amount: tx.amount,
+                payments: payments_from_net(&tx.payments)?,
+                fee: tx.fee,
+                nonce: tx.nonce as u64,
+                lock_time: tx.lock_time as u64,
+                data: FromHex::from_hex(&tx.data)?,
+                timestamp: tx.timestamp,
+                signature_scheme: tx.signature_scheme as u8
             },
-            signature: signature,
-        },
-    })
+            signature: coinbase
+        };
+
+        return Ok(Transaction {
+            id: tx_signed.get_id()?,
+            transaction: tx_signed
+        });
+    }
+
+    from(
+        &tx.sender_addr,
+        &tx.sender_pubkey,
+        &tx.receiver_addr,
+        tx.amount,
+        &tx.payments,
+        tx.fee,
+        tx.nonce as u64,
+        tx.lock_time as u64,
+        &tx.data,
+        tx.timestamp,
+        tx.signature_scheme as u8,
+        &tx.signature
+    )
 }
 
-pub fn coinbase() -> Result<Transaction, CoreError> {
-    println!("CREATE COINBASE TRANSACTION");
-    // retrieve wallet entry from storage
-    // XXX this is ugly, we read for the address and then read again for private_key and public_key
-    // we should do everything at once without reading twice
-    let cfg = jfs::Config {
-        pretty: true,
-        indent: 4,
-        single: true
-    };
-    let storage = jfs::Store::new_with_cfg("storage/wallet", cfg).unwrap();
-    let wallets = storage.all::<NetKeyPair>().unwrap();
+fn payments_from_net(payments: &Vec<NetPayment>) -> Result<Vec<Payment>, CoreError> {
+    let mut decoded: Vec<Payment> = Vec::new();
+    for payment in payments {
+        decoded.push(Payment {
+            address: payment.address.from_base58()?,
+            amount: payment.amount
+        });
+    }
 
-    let wallet = wallets.iter().nth(0);
+    Ok(decoded)
+}
 
-    let address = match wallet {
-        Some(w) => w.0.from_base58()?,
-        None => return Err(CoreError::WalletError)
-    };
+// create the coinbase transaction of the block at the given height, paying the subsidy and the fees to an address
+pub fn coinbase(id: i32, fees: Amount, address: Vec<u8>) -> Result<Transaction, CoreError> {
+    println!("CREATE COINBASE TRANSACTION");
 
     // create coinbase value
     let coinbase: Vec<u8> = vec![0];
@@ -258,8 +462,16 @@ pub fn coinbase() -> Result<Transaction, CoreError> {
         sender_addr: coinbase.clone(),
         sender_pubkey: coinbase.clone(),
         receiver_addr: address,
-        amount: 50, // XXX FIXME amount is hardcoded for now
-        timestamp: timestamp
+        amount: monetary::get_max_coinbase_amount(id, fees).ok_or(CoreError::AmountOverflow)?,
+        payments: Vec::new(),
+        fee: Amount::zero(),
+        // the height, so that coinbases of different blocks never share an id
+        nonce: id as u64,
+        lock_time: 0,
+        data: Vec::new(),
+        timestamp: timestamp,
+        // a coinbase has no signature
+        signature_scheme: SignatureScheme::Schnorr.to_tag()
     };
 
     let tx_signed = TransactionSigned {
@@ -277,70 +489,13 @@ pub fn coinbase() -> Result<Transaction, CoreError> {
     })
 }
 
-// TODO rewrite this with redis
-// XXX maybe return a NetTransaction directly?
-// read all cached database transactions
-pub fn read_db() -> Result<Vec<Transaction>, CoreError> {
-    println!("READ TRANSACTIONS [DB]");
-    let conn = Connection::open("db/storage.db")?;
-
-    let mut transactions: Vec<Transaction> = Vec::new();
-
-    let mut stmt = conn.prepare(
-        "SELECT id, sender_addr, sender_pubkey, receiver_addr, amount, timestamp, signature
-        FROM transactions"
-    )?;
-
-    let net_txs = stmt.query_map(&[], |row| {
-        let id: String = row.get(0);
-        let sender_addr: String = row.get(1);
-        let sender_pubkey: String = row.get(2);
-        let receiver_addr: String = row.get(3);
-        let amount: i32 = row.get(4);
-        let timestamp: i64 = row.get(5);
-        let signature: String = row.get(6);
-
-        NetTransaction {
-            id: id,
-            sender_addr: sender_addr,
-            sender_pubkey: sender_pubkey,
-            receiver_addr: receiver_addr,
-            amount: amount,
-            timestamp: timestamp,
-            signature: signature
-        }
-    })?;
-
-    for net_tx in net_txs {
-        let net_tx = net_tx?;
-
-        let tx = Transaction {
-            id: FromHex::from_hex(net_tx.id)?,
-            transaction: TransactionSigned {
-                content: TransactionContent {
-                    sender_addr: net_tx.sender_addr.from_base58()?,
-                    sender_pubkey: FromHex::from_hex(net_tx.sender_pubkey)?,
-                    receiver_addr: net_tx.receiver_addr.from_base58()?,
-                    amount: net_tx.amount,
-                    timestamp: net_tx.timestamp
-                },
-                signature: FromHex::from_hex(net_tx.signature)?
-            }
-        };
-
-        transactions.push(tx);
-    }
-
-    Ok(transactions)
+// get the minimum fee of a relayed transaction of the given size
+pub fn get_min_relay_fee(size: usize) -> Amount {
+    // can't overflow for any size a transaction can have
+    Amount::from_base_units(MIN_RELAY_FEE_RATE * (size / 1000 + 1) as u64).unwrap()
 }
 
-// delete all cached transactions from database
-pub fn clean_db() -> Result<(), CoreError> {
-    println!("CLEAN TRANSACTIONS [DB]");
-    // TODO rewrite this with connection pools
-    // TODO get the db address string from config.json
-    let conn = Connection::open("db/storage.db")?;
-
-    conn.execute("DELETE FROM transactions", &[])?;
-    Ok(())
+// get the fee paid per kilobyte by a transaction of the given size
+pub fn get_fee_rate(fee: Amount, size: usize) -> u64 {
+    fee.to_base_units().saturating_mul(1000) / size as u64
 }
@@ -0,0 +1,512 @@
+use std::collections::{HashMap, HashSet};
+use bincode::{serialize, deserialize, Infinite};
+use sha2::{Sha256, Digest};
+use postgres::GenericConnection;
+use base58::{FromBase58, ToBase58};
+use hex::{FromHex, ToHex};
+use secp256k1;
+use secp256k1::key::SecretKey;
+
+use amount::Amount;
+use errors::{CoreError, BlockError};
+use blockchain;
+use mempool;
+use monetary;
+use signature;
+use signature::SignatureScheme;
+use transactions;
+use utils;
+use wallet;
+
+// transactions spending the outputs of previous transactions
+// blocks carry them from version 2 on (see `blocks::get_block_version`), next to the account transactions
+
+// version of the transaction format
+// version 1 inputs have no signature scheme, they are all signed with `SignatureScheme::Schnorr`,
+// and version 1 coinbases don't commit to their height
+pub const UTXO_TX_VERSION_LEGACY: u32 = 1;
+pub const UTXO_TX_VERSION: u32 = 2;
+
+// unspent outputs as of the main chain's tip
+// `spent_outputs` keeps the outputs spent by every main chain block to restore them when the block is disconnected
+pub const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS utxos (
+        tx_id TEXT NOT NULL,
+        output_index INTEGER NOT NULL,
+        address TEXT NOT NULL,
+        amount BIGINT NOT NULL,
+        PRIMARY KEY (tx_id, output_index)
+    );
+
+    CREATE INDEX IF NOT EXISTS utxos_address_idx ON utxos(address);
+
+    CREATE TABLE IF NOT EXISTS spent_outputs (
+        block_hash TEXT NOT NULL,
+        tx_id TEXT NOT NULL,
+        output_index INTEGER NOT NULL,
+        address TEXT NOT NULL,
+        amount BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS spent_outputs_block_hash_idx ON spent_outputs(block_hash);
+";
+
+// reference to an output of a previous transaction
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+pub struct OutPoint {
+    pub tx_id: Vec<u8>,
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TxInput {
+    pub previous_output: OutPoint,
+    pub pubkey: Vec<u8>, // must hash to the address of the spent output
+    pub signature_scheme: u8, // see `SignatureScheme`, covered by the signature
+    pub signature: Vec<u8>,
+}
+
+// input of a version 1 transaction, which is serialized without a signature scheme
+// stored transactions keep their serialization, hence their id
+#[derive(Serialize, Deserialize)]
+struct LegacyTxInput {
+    previous_output: OutPoint,
+    pubkey: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyUtxoTransaction {
+    version: u32,
+    inputs: Vec<LegacyTxInput>,
+    outputs: Vec<TxOutput>,
+    timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TxOutput {
+    pub address: Vec<u8>,
+    pub amount: Amount,
+}
+
+// the id of the transaction is the hash of its serialization, signatures included
+// a coinbase has no input
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct UtxoTransaction {
+    pub version: u32,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub timestamp: i64,
+    // height of the block of a coinbase, so that two coinbases can't have the same id, null for other transactions
+    pub height: u32,
+}
+
+impl UtxoTransaction {
+    // decode a transaction from its hex serialization, as carried by network blocks
+    pub fn from_raw(raw: &String) -> Result<UtxoTransaction, CoreError> {
+        let bytes: Vec<u8> = FromHex::from_hex(raw)?;
+
+        // the version comes first and tells the format of the inputs
+        let version: u32 = deserialize(&bytes[..])?;
+        if version != UTXO_TX_VERSION_LEGACY {
+            let tx: UtxoTransaction = deserialize(&bytes[..])?;
+            return Ok(tx);
+        }
+
+        let legacy: LegacyUtxoTransaction = deserialize(&bytes[..])?;
+        Ok(UtxoTransaction {
+            version: legacy.version,
+            inputs: legacy.inputs.into_iter().map(|input| {
+                TxInput {
+                    previous_output: input.previous_output,
+                    pubkey: input.pubkey,
+                    signature_scheme: SignatureScheme::Schnorr.to_tag(),
+                    signature: input.signature
+                }
+            }).collect(),
+            outputs: legacy.outputs,
+            timestamp: legacy.timestamp,
+            height: 0
+        })
+    }
+
+    pub fn to_raw(&self) -> String {
+        self.to_bytes().to_hex()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        // can't fail as the size is unbounded
+        if self.version != UTXO_TX_VERSION_LEGACY {
+            return serialize(&self, Infinite).unwrap();
+        }
+
+        let legacy = LegacyUtxoTransaction {
+            version: self.version,
+            inputs: self.inputs.iter().map(|input| {
+                LegacyTxInput {
+                    previous_output: input.previous_output.clone(),
+                    pubkey: input.pubkey.clone(),
+                    signature: input.signature.clone()
+                }
+            }).collect(),
+            outputs: self.outputs.clone(),
+            timestamp: self.timestamp
+        };
+        serialize(&legacy, Infinite).unwrap()
+    }
+
+    // hash the serialized transaction to get its id
+    pub fn get_id(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.input(&self.to_bytes());
+        hasher.result().as_slice().to_vec()
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // get the amount paid to every output, none if it overflows
+    pub fn get_output_amount(&self) -> Option<Amount> {
+        Amount::checked_sum(self.outputs.iter().map(|output| output.amount))
+    }
+
+    // hash signed by the input at the given index: the transaction without any signature, followed by the index
+    fn get_signature_hash(&self, index: usize) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        for input in unsigned.inputs.iter_mut() {
+            input.signature = Vec::new();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.input(&unsigned.to_bytes());
+        hasher.input(&serialize(&(index as u32), Infinite).unwrap());
+        hasher.result().as_slice().to_vec()
+    }
+
+    // sign the input at the given index with the key owning the output it spends, under the scheme of the input
+    pub fn sign_input(&mut self, index: usize, private_key: &SecretKey) -> Result<(), CoreError> {
+        let secp = secp256k1::Secp256k1::new();
+        let input = secp256k1::Message::from_slice(&self.get_signature_hash(index))?;
+        let scheme = SignatureScheme::from_tag(self.inputs[index].signature_scheme).ok_or(CoreError::CryptoError)?;
+
+        self.inputs[index].signature = signature::sign(&secp, scheme, &input, private_key)?;
+
+        Ok(())
+    }
+
+    // check the format of the transaction and the signature of every input
+    // the spent outputs are checked against the UTXO set by `UtxoView::apply`
+    pub fn is_valid(&self) -> Result<bool, CoreError> {
+        self.is_valid_with(&secp256k1::Secp256k1::new())
+    }
+
+    // same as `is_valid`, with a context shared by the caller instead of a new one
+    pub fn is_valid_with(&self, secp: &secp256k1::Secp256k1) -> Result<bool, CoreError> {
+        println!("VERIFY UTXO TRANSACTION");
+
+        if (self.version != UTXO_TX_VERSION && self.version != UTXO_TX_VERSION_LEGACY) || self.outputs.is_empty() {
+            return Ok(false);
+        }
+
+        // a version 1 transaction can't carry another scheme or a height, they wouldn't be serialized
+        if self.version == UTXO_TX_VERSION_LEGACY
+            && (self.height != 0 || self.inputs.iter().any(|input| input.signature_scheme != SignatureScheme::Schnorr.to_tag())) {
+            return Ok(false);
+        }
+
+        // only a coinbase commits to a height, checked against its block by `validation`
+        if !self.is_coinbase() && self.height != 0 {
+            return Ok(false);
+        }
+
+        // every output gets something, no more than the max supply, and the total must fit in an amount
+        let max_supply: Amount = monetary::get_max_supply();
+        if self.outputs.iter().any(|output| output.amount.is_zero() || output.amount > max_supply)
+            || self.get_output_amount().is_none() {
+            return Ok(false);
+        }
+
+        if self.is_coinbase() {
+            return Ok(true);
+        }
+
+        // an output can't be spent twice by the same transaction
+        let mut spent: HashSet<&OutPoint> = HashSet::new();
+        for input in &self.inputs {
+            if !spent.insert(&input.previous_output) {
+                return Ok(false);
+            }
+        }
+
+        // an unknown scheme or a key that doesn't parse makes the transaction invalid, not the check
+        for (i, input) in self.inputs.iter().enumerate() {
+            let scheme = match SignatureScheme::from_tag(input.signature_scheme) {
+                Some(scheme) => scheme,
+                None => return Ok(false)
+            };
+            let message = secp256k1::Message::from_slice(&self.get_signature_hash(i))?;
+
+            if !signature::verify(secp, scheme, &message, &input.signature, &input.pubkey) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+// outputs created and spent by the transactions applied so far on top of the UTXO set
+pub struct UtxoView {
+    created: HashMap<OutPoint, TxOutput>,
+    spent: HashSet<OutPoint>,
+}
+
+impl UtxoView {
+    pub fn new() -> UtxoView {
+        UtxoView {
+            created: HashMap::new(),
+            spent: HashSet::new()
+        }
+    }
+
+    // check that the inputs of a transaction exist, aren't spent yet, belong to its keys and cover its outputs
+    // then spend them and add its outputs to the view, returns the fee (inputs minus outputs)
+    // the view is left unchanged if the transaction doesn't apply
+    pub fn apply<C: GenericConnection>(&mut self, conn: &C, tx: &UtxoTransaction) -> Result<Amount, BlockError> {
+        let id: Vec<u8> = tx.get_id();
+
+        // outputs can't be created again while they are unspent (two identical coinbases)
+        let first_output = OutPoint { tx_id: id.clone(), index: 0 };
+        if self.created.contains_key(&first_output) || get_output(conn, &first_output)?.is_some() {
+            return Err(BlockError::DuplicateTransaction(id.to_hex()));
+        }
+
+        let mut input_amount: Amount = Amount::zero();
+
+        for input in &tx.inputs {
+            let outpoint = &input.previous_output;
+
+            if self.spent.contains(outpoint) {
+                return Err(BlockError::MissingInput(id.to_hex()));
+            }
+
+            let output: TxOutput = match self.created.get(outpoint) {
+                Some(output) => output.clone(),
+                None => match get_output(conn, outpoint)? {
+                    Some(output) => output,
+                    None => return Err(BlockError::MissingInput(id.to_hex()))
+                }
+            };
+
+            // the signature was made with the key owning the output
+            if wallet::get_address(&input.pubkey) != output.address {
+                return Err(BlockError::InvalidSignature(id.to_hex()));
+            }
+
+            input_amount = match input_amount.checked_add(output.amount) {
+                Some(amount) => amount,
+                None => return Err(BlockError::AmountOverflow(id.to_hex()))
+            };
+        }
+
+        let output_amount: Amount = match tx.get_output_amount() {
+            Some(amount) => amount,
+            None => return Err(BlockError::AmountOverflow(id.to_hex()))
+        };
+        if !tx.is_coinbase() && input_amount < output_amount {
+            return Err(BlockError::InsufficientFunds(id.to_hex()));
+        }
+
+        for input in &tx.inputs {
+            self.spent.insert(input.previous_output.clone());
+        }
+
+        for (i, output) in tx.outputs.iter().enumerate() {
+            self.created.insert(OutPoint { tx_id: id.clone(), index: i as u32 }, output.clone());
+        }
+
+        if tx.is_coinbase() {
+            Ok(Amount::zero())
+        } else {
+            // can't underflow as the inputs cover the outputs
+            Ok(input_amount.saturating_sub(output_amount))
+        }
+    }
+
+    // check if an output is spent by one of the applied transactions
+    pub fn is_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent.contains(outpoint)
+    }
+}
+
+// create a transaction paying the given outputs (change included) from the given outputs of a wallet
+// every input is signed with the wallet's key under the given scheme
+pub fn new(
+    private_key: SecretKey,
+    public_key: Vec<u8>,
+    signature_scheme: SignatureScheme,
+    spent_outputs: Vec<OutPoint>,
+    outputs: Vec<TxOutput>
+) -> Result<UtxoTransaction, CoreError> {
+    println!("CREATE UTXO TRANSACTION");
+
+    let mut tx = UtxoTransaction {
+        version: UTXO_TX_VERSION,
+        inputs: spent_outputs.into_iter().map(|outpoint| {
+            TxInput {
+                previous_output: outpoint,
+                pubkey: public_key.clone(),
+                signature_scheme: signature_scheme.to_tag(),
+                signature: Vec::new()
+            }
+        }).collect(),
+        outputs: outputs,
+        timestamp: utils::get_current_timestamp(),
+        height: 0
+    };
+
+    for i in 0..tx.inputs.len() {
+        tx.sign_input(i, &private_key)?;
+    }
+
+    Ok(tx)
+}
+
+// create the coinbase transaction of the block at the given height, paying the subsidy and the fees to an address
+// the height makes its id unique, as the nonce does for the coinbase of a version 1 block
+pub fn coinbase(id: i32, amount: Amount, address: Vec<u8>) -> UtxoTransaction {
+    println!("CREATE UTXO COINBASE TRANSACTION");
+
+    UtxoTransaction {
+        version: UTXO_TX_VERSION,
+        inputs: Vec::new(),
+        outputs: vec![TxOutput { address: address, amount: amount }],
+        timestamp: utils::get_current_timestamp(),
+        height: id as u32
+    }
+}
+
+// apply the transactions of a block being connected to the UTXO set
+// or undo them if the block is being disconnected
+pub fn apply_transactions<C: GenericConnection>(
+    conn: &C,
+    block_hash: &String,
+    transactions: &Vec<UtxoTransaction>,
+    revert: bool
+) -> Result<(), CoreError> {
+    if revert {
+        // restore the outputs spent by the block, then drop the ones it created
+        // (outputs created and spent within the block are restored and dropped again)
+        conn.execute(
+            "INSERT INTO utxos(tx_id, output_index, address, amount)
+            SELECT tx_id, output_index, address, amount FROM spent_outputs WHERE block_hash = $1",
+            &[block_hash]
+        )?;
+        conn.execute("DELETE FROM spent_outputs WHERE block_hash = $1", &[block_hash])?;
+
+        for tx in transactions {
+            conn.execute("DELETE FROM utxos WHERE tx_id = $1", &[&tx.get_id().to_hex()])?;
+        }
+
+        return Ok(());
+    }
+
+    for tx in transactions {
+        for input in &tx.inputs {
+            let outpoint = &input.previous_output;
+
+            conn.execute(
+                "WITH spent AS (
+                    DELETE FROM utxos WHERE tx_id = $2 AND output_index = $3
+                    RETURNING tx_id, output_index, address, amount
+                )
+                INSERT INTO spent_outputs(block_hash, tx_id, output_index, address, amount)
+                SELECT $1, tx_id, output_index, address, amount FROM spent",
+                &[block_hash, &outpoint.tx_id.to_hex(), &(outpoint.index as i32)]
+            )?;
+        }
+
+        let id: String = tx.get_id().to_hex();
+
+        for (i, output) in tx.outputs.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO utxos(tx_id, output_index, address, amount) VALUES($1, $2, $3, $4)",
+                &[&id, &(i as i32), &output.address.to_base58(), &output.amount]
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// get an unspent output of the main chain
+pub fn get_output<C: GenericConnection>(conn: &C, outpoint: &OutPoint) -> Result<Option<TxOutput>, CoreError> {
+    let rows = conn.query(
+        "SELECT address, amount FROM utxos WHERE tx_id = $1 AND output_index = $2",
+        &[&outpoint.tx_id.to_hex(), &(outpoint.index as i32)]
+    )?;
+
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    let address: String = row.get(0);
+
+    Ok(Some(TxOutput {
+        address: address.from_base58()?,
+        amount: row.get(1)
+    }))
+}
+
+// get the unspent outputs of an address as of the main chain's tip
+pub fn get_unspent_outputs(address: &String) -> Result<Vec<(OutPoint, TxOutput)>, CoreError> {
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    let rows = conn.query(
+        "SELECT tx_id, output_index, amount FROM utxos WHERE address = $1 ORDER BY amount DESC",
+        &[address]
+    )?;
+
+    let address_bytes: Vec<u8> = address.from_base58()?;
+    let mut outputs: Vec<(OutPoint, TxOutput)> = Vec::new();
+
+    for row in rows.iter() {
+        let tx_id: String = row.get(0);
+        let index: i32 = row.get(1);
+
+        outputs.push((
+            OutPoint { tx_id: FromHex::from_hex(tx_id)?, index: index as u32 },
+            TxOutput { address: address_bytes.clone(), amount: row.get(2) }
+        ));
+    }
+
+    Ok(outputs)
+}
+
+// get the pending transactions which still apply on top of the main chain, in the order they were received,
+// with their fee, along with the view of the outputs they spend and create
+pub fn get_pending() -> Result<(Vec<(UtxoTransaction, Amount)>, UtxoView), CoreError> {
+    let pool = blockchain::get_db_pool()?;
+    let conn = pool.get().unwrap();
+
+    let mut view = UtxoView::new();
+    let mut pending: Vec<(UtxoTransaction, Amount)> = Vec::new();
+
+    for tx in mempool::get_utxo_transactions() {
+        match view.apply(&*conn, &tx) {
+            Ok(fee) => pending.push((tx, fee)),
+            Err(BlockError::CoreError) => return Err(CoreError::DatabaseError),
+            // the transaction conflicts with the chain or with a previous pending transaction
+            Err(_) => {}
+        }
+    }
+
+    Ok((pending, view))
+}
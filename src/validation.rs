@@ -0,0 +1,326 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use postgres::GenericConnection;
+use hex::ToHex;
+use num_cpus;
+use secp256k1;
+
+use amount::Amount;
+use blocks::{self, Block};
+use blockchain;
+use merkle;
+use monetary;
+use state;
+use transactions::Transaction;
+use difficulty;
+use utils;
+use utxo::{self, UtxoTransaction, UtxoView};
+use net::{NetBlock, NetTransaction};
+use errors::{BlockError, CoreError};
+
+// how far in the future a block can be timestamped (ms)
+// small against the timespan of a retarget interval, so that timestamps can't warp the difficulty
+pub const MAX_FUTURE_DRIFT: i64 = 2 * difficulty::TARGET_BLOCK_TIME;
+
+// transaction of a block whose signatures are checked by the workers
+enum SignedTransaction {
+    Account(Transaction),
+    Utxo(UtxoTransaction)
+}
+
+impl SignedTransaction {
+    fn get_id(&self) -> Vec<u8> {
+        match *self {
+            SignedTransaction::Account(ref tx) => tx.id.clone(),
+            SignedTransaction::Utxo(ref tx) => tx.get_id()
+        }
+    }
+
+    fn is_valid_with(&self, secp: &secp256k1::Secp256k1) -> Result<bool, CoreError> {
+        match *self {
+            SignedTransaction::Account(ref tx) => tx.is_valid_with(secp),
+            SignedTransaction::Utxo(ref tx) => tx.is_valid_with(secp)
+        }
+    }
+}
+
+// check a received block against its header, its transactions and the chain
+// returns the reason of the rejection if the block isn't valid
+pub fn validate_block(block: &Block, mined_hash: &Vec<u8>) -> Result<(), BlockError> {
+    let header = &block.header;
+
+    // the parent must be a known block (at least the genesis) and the height must follow it
+    let parent = match blockchain::get_header_by_hash(&header.previous_hash)? {
+        Some(parent) => parent,
+        None => return Err(BlockError::UnknownParent)
+    };
+
+    if header.id != parent.id + 1 {
+        return Err(BlockError::InvalidHeight);
+    }
+
+    // the timestamp must be after the median of the last blocks and can't be far in the future,
+    // as the difficulty depends on it
+    let median_time_past: i64 = blockchain::get_median_time_past(&parent)?;
+    if header.timestamp <= median_time_past
+        || header.timestamp > utils::get_current_timestamp().saturating_add(MAX_FUTURE_DRIFT) {
+        return Err(BlockError::InvalidTimestamp);
+    }
+
+    // the height decides which transaction model the block uses
+    if header.version != blocks::get_block_version(header.id) {
+        return Err(BlockError::InvalidVersion);
+    }
+
+    // check the hash and the difficulty expected at this height
+    if !blocks::verify(header, mined_hash)? {
+        return Err(BlockError::InvalidProofOfWork);
+    }
+
+    if block.get_size() > blocks::MAX_BLOCK_SIZE {
+        return Err(BlockError::BlockTooLarge);
+    }
+
+    // the merkle root must commit to the transactions actually included
+    if merkle::get_merkle_root(&block.get_tx_hash_list()) != header.merkle_root {
+        return Err(BlockError::InvalidMerkleRoot);
+    }
+
+    validate_transactions(block, median_time_past)
+}
+
+// check that the ids a block was received with are the ones derived from its transactions
+// a block can't relay a transaction under another id, which would poison lookups by id
+pub fn validate_transaction_ids(net_block: &NetBlock, block: &Block) -> Result<(), BlockError> {
+    for (net_tx, tx) in net_block.transactions.iter().zip(block.transactions.iter()) {
+        if !tx.has_id(&net_tx.id) {
+            return Err(BlockError::InvalidTransactionId(net_tx.id.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+// check the coinbase, duplicates, lock times and signatures of the block's transactions
+// the coinbase is the first account transaction of a version 1 block, the first UTXO transaction of a version 2 block
+// time lock times are checked against the median time past of the parent, not the timestamp chosen by the miner
+fn validate_transactions(block: &Block, median_time_past: i64) -> Result<(), BlockError> {
+    let utxo_block: bool = block.header.version == blocks::BLOCK_VERSION_UTXO;
+
+    // version 1 blocks can't carry UTXO transactions
+    if !utxo_block && !block.utxo_transactions.is_empty() {
+        return Err(BlockError::InvalidVersion);
+    }
+
+    // exactly one coinbase, at index 0
+    let has_coinbase: bool = if utxo_block {
+        block.utxo_transactions.first().map_or(false, |tx| tx.is_coinbase())
+    } else {
+        block.transactions.first().map_or(false, |tx| tx.is_coinbase())
+    };
+    if !has_coinbase {
+        return Err(BlockError::MissingCoinbase);
+    }
+
+    let mut ids: HashSet<Vec<u8>> = HashSet::new();
+    let mut fees: Amount = Amount::zero();
+
+    for (i, tx) in block.transactions.iter().enumerate() {
+        if !ids.insert(tx.id.clone()) {
+            return Err(BlockError::DuplicateTransaction(tx.id.to_hex()));
+        }
+
+        if i == 0 && !utxo_block {
+            continue;
+        }
+
+        if tx.is_coinbase() {
+            return Err(BlockError::MisplacedCoinbase);
+        }
+
+        // a locked transaction can't be included before the height or time it names
+        if !tx.is_final(block.header.id, median_time_past) {
+            return Err(BlockError::ImmatureTransaction(tx.id.to_hex()));
+        }
+
+        fees = match fees.checked_add(tx.get_fee()) {
+            Some(fees) => fees,
+            None => return Err(BlockError::AmountOverflow(tx.id.to_hex()))
+        };
+    }
+
+    for (i, tx) in block.utxo_transactions.iter().enumerate() {
+        let id: Vec<u8> = tx.get_id();
+
+        if !ids.insert(id.clone()) {
+            return Err(BlockError::DuplicateTransaction(id.to_hex()));
+        }
+
+        if i > 0 && tx.is_coinbase() {
+            return Err(BlockError::MisplacedCoinbase);
+        }
+
+        // the height keeps the coinbase's id from being the one of another block's coinbase
+        // (the outputs of a transaction are restored and dropped by its id)
+        if i == 0 && tx.version != utxo::UTXO_TX_VERSION_LEGACY && tx.height != block.header.id as u32 {
+            return Err(BlockError::InvalidCoinbaseHeight);
+        }
+    }
+
+    verify_signatures(block)?;
+
+    // the coinbase can't claim more than the subsidy and the fees of the block
+    // the fees of UTXO transactions depend on the outputs they spend, version 2 coinbases are checked with the state
+    if !utxo_block {
+        let coinbase_amount = block.transactions[0].transaction.content.get_total_amount();
+        let max_amount = monetary::get_max_coinbase_amount(block.header.id, fees);
+
+        match (coinbase_amount, max_amount) {
+            (Some(amount), Some(max_amount)) if amount <= max_amount => (),
+            _ => return Err(BlockError::InvalidCoinbaseAmount)
+        }
+    }
+
+    Ok(())
+}
+
+// check the transactions of the block (their format and signatures) using all cores, with one context shared by the workers
+// worker `i` checks the transactions `i`, `i + n`, `i + 2n`... and skips the ones after an invalid transaction
+// already found, so that the first invalid transaction of the block is always the one reported
+// none of the schemes has a batch verifier in the libraries used, every signature is checked on its own
+fn verify_signatures(block: &Block) -> Result<(), BlockError> {
+    let mut transactions: Vec<SignedTransaction> = Vec::new();
+    for tx in &block.transactions {
+        // the coinbase of a version 1 block has no signature and its amount is checked with the fees
+        if !tx.is_coinbase() {
+            transactions.push(SignedTransaction::Account(tx.clone()));
+        }
+    }
+    for tx in &block.utxo_transactions {
+        transactions.push(SignedTransaction::Utxo(tx.clone()));
+    }
+
+    let workers: usize = cmp::min(num_cpus::get(), transactions.len());
+    if workers == 0 {
+        return Ok(());
+    }
+
+    let secp = Arc::new(secp256k1::Secp256k1::new());
+    let transactions = Arc::new(transactions);
+    // index of the first invalid transaction found so far, the number of transactions if none
+    let first_invalid = Arc::new(AtomicUsize::new(transactions.len()));
+
+    let mut handles = Vec::new();
+
+    for i in 0..workers {
+        let secp = secp.clone();
+        let transactions = transactions.clone();
+        let first_invalid = first_invalid.clone();
+
+        handles.push(thread::spawn(move || {
+            let mut index = i;
+
+            while index < transactions.len() && index < first_invalid.load(Ordering::SeqCst) {
+                // a transaction which can't be checked is invalid too, the block reports it
+                match transactions[index].is_valid_with(&secp) {
+                    Ok(true) => {},
+                    _ => {
+                        first_invalid.fetch_min(index, Ordering::SeqCst);
+                        return;
+                    }
+                }
+
+                index += workers;
+            }
+        }));
+    }
+
+    // only a worker that panicked leaves transactions unchecked
+    let mut checked: bool = true;
+    for handle in handles {
+        if handle.join().is_err() {
+            checked = false;
+        }
+    }
+    if !checked {
+        return Err(BlockError::CoreError);
+    }
+
+    let index: usize = first_invalid.load(Ordering::SeqCst);
+    match transactions.get(index) {
+        Some(tx) => Err(BlockError::InvalidSignature(tx.get_id().to_hex())),
+        None => Ok(())
+    }
+}
+
+// check the transactions of a block being connected against the state left by its parent:
+// none of them is already in the main chain, every sender can afford what it spends
+// and uses the nonces following its last transaction, every UTXO transaction spends existing unspent outputs
+pub fn validate_block_state<C: GenericConnection>(
+    conn: &C,
+    id: i32,
+    transactions: &Vec<NetTransaction>,
+    utxo_transactions: &Vec<UtxoTransaction>
+) -> Result<(), BlockError> {
+    // amount spent and number of transactions of every sender so far in the block
+    let mut spent: HashMap<&String, Amount> = HashMap::new();
+    let mut sent: HashMap<&String, u64> = HashMap::new();
+    // fees of the account transactions and of the UTXO transactions, collected by a version 2 coinbase
+    let mut fees: Amount = Amount::zero();
+
+    for tx in transactions {
+        if blockchain::is_in_main_chain(conn, &tx.id)? {
+            return Err(BlockError::DuplicateTransaction(tx.id.clone()));
+        }
+
+        // coinbase transactions have no sender
+        if tx.sender_addr == "0" {
+            continue;
+        }
+
+        let total = spent.entry(&tx.sender_addr).or_insert(Amount::zero());
+        let new_total: Option<Amount> = tx.get_debit().and_then(|debit| total.checked_add(debit));
+        *total = match new_total {
+            Some(total) => total,
+            None => return Err(BlockError::AmountOverflow(tx.id.clone()))
+        };
+        fees = match fees.checked_add(tx.fee) {
+            Some(fees) => fees,
+            None => return Err(BlockError::AmountOverflow(tx.id.clone()))
+        };
+
+        if state::get_balance(conn, &tx.sender_addr)? < *total {
+            return Err(BlockError::InsufficientFunds(tx.id.clone()));
+        }
+
+        let count = sent.entry(&tx.sender_addr).or_insert(0);
+        if tx.nonce as u64 != state::get_nonce(conn, &tx.sender_addr)? + *count {
+            return Err(BlockError::InvalidNonce(tx.id.clone()));
+        }
+        *count += 1;
+    }
+
+    // a UTXO transaction can spend the outputs of the previous ones in the block
+    let mut view = UtxoView::new();
+
+    for tx in utxo_transactions {
+        let fee: Amount = view.apply(conn, tx)?;
+        fees = match fees.checked_add(fee) {
+            Some(fees) => fees,
+            None => return Err(BlockError::AmountOverflow(tx.get_id().to_hex()))
+        };
+    }
+
+    // the coinbase of a version 2 block can't claim more than the subsidy and the fees of the block
+    if let Some(coinbase) = utxo_transactions.first() {
+        match (coinbase.get_output_amount(), monetary::get_max_coinbase_amount(id, fees)) {
+            (Some(amount), Some(max_amount)) if amount <= max_amount => (),
+            _ => return Err(BlockError::InvalidCoinbaseAmount)
+        }
+    }
+
+    Ok(())
+}
@@ -10,6 +10,11 @@ use jfs;
 use net::NetKeyPair;
 use errors::CoreError;
 
+// size of an address (hash of the public key)
+pub const ADDRESS_SIZE: usize = 32;
+// size of a compressed public key
+pub const PUBLIC_KEY_SIZE: usize = 33;
+
 pub struct Wallet {
     pub keypair: KeyPair,
     pub address: Vec<u8>,
@@ -30,22 +35,24 @@ pub fn get_new_wallet() -> Result<Wallet, CoreError> {
     // serialize and compress the public key
     let public_key_compressed = public_key.serialize_vec(&secp, true);
 
-    // hash the public key to get the address
-    let mut hasher = Sha256::new();
-    hasher.input(&public_key_compressed);
-    let hash = hasher.result();
-
     let keypair = KeyPair {
         private_key: private_key,
         public_key: public_key_compressed.as_slice().to_vec()
     };
 
     Ok(Wallet {
-        keypair: keypair,
-        address: hash.as_slice().to_vec()
+        address: get_address(&keypair.public_key),
+        keypair: keypair
     })
 }
 
+// hash a public key, or a multisig policy, to get its address
+pub fn get_address(public_key: &Vec<u8>) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.input(public_key);
+    hasher.result().as_slice().to_vec()
+}
+
 // return the wallet associated with the given address
 // TODO handle if there is no wallet associated
 pub fn get_wallet(address: &String) -> Result<Wallet, CoreError> {
@@ -75,3 +82,19 @@ pub fn get_wallet(address: &String) -> Result<Wallet, CoreError> {
         address: address.from_base58()?
     })
 }
+
+// return the address of the first wallet in storage
+pub fn get_default_address() -> Result<Vec<u8>, CoreError> {
+    let cfg = jfs::Config {
+        pretty: true,
+        indent: 4,
+        single: true
+    };
+    let storage = jfs::Store::new_with_cfg("storage/wallet", cfg).unwrap();
+    let wallets = storage.all::<NetKeyPair>().unwrap();
+
+    match wallets.iter().nth(0) {
+        Some(w) => Ok(w.0.from_base58()?),
+        None => Err(CoreError::WalletError)
+    }
+}